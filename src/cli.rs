@@ -1,65 +1,826 @@
 //! Implements the CLI interface.
 use std::convert::TryFrom;
-use std::num::ParseIntError;
-use std::path::PathBuf;
+use std::path::{self, PathBuf};
 use std::str::FromStr;
 
+use log::LevelFilter;
 use structopt::StructOpt;
 use thiserror::Error;
 
-use crate::pascal_voc::PrepareOpts;
+use regex::{Error as RegexError, Regex};
+
+use crate::config::PrepareConfig;
+use tftools::pascal_voc::{
+    AnchorsOpts, AnnotationField, CheckLabelsOpts, ClassDefinitions, ConvertOpts, DedupeOpts,
+    DuplicateHandling, DuplicateOpts, FailOn, GroupBy, ImageEncoding, LabelMap, LabelMapError,
+    LabelMapFormat, LabelRules, LabelRulesError, MergeOpts, PredicateError, PrepareOpts,
+    PreviewOpts, PreviewSource, ReportFormat, ReshardOpts, Schema, SchemaError, Shuffle, SplitMode,
+    SplitOpts, SplitSpec, StatsFormat, StatsOpts,
+};
+
+#[derive(StructOpt, Debug)]
+pub struct Cli {
+    /// Print what is being done, repeat for more details (-vv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences), global = true)]
+    pub verbose: u8,
+    /// Only log errors, progress bars are hidden
+    #[structopt(short = "q", long = "quiet", global = true)]
+    pub quiet: bool,
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+impl Cli {
+    /// Level of the messages logged by tftools, warnings by default
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Warn,
+            (false, 1) => LevelFilter::Info,
+            (false, 2) => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
 
 #[derive(StructOpt, Debug)]
 pub enum Command {
     /// Use a PASCAL-VOC dataset
     PascalVoc(PascalVoc),
+    /// Work with label maps
+    LabelMap(LabelMapCommand),
+    /// Work with tfrecord files
+    #[structopt(name = "tfrecord")]
+    TfRecord(TfRecordCommand),
+}
+
+#[derive(StructOpt, Debug)]
+pub enum TfRecordCommand {
+    /// Check that the class IDs and texts of tfrecord files agree with a label map
+    CheckLabels(CheckLabelsCliOpts),
+    /// Merge tfrecord files and rewrite them into N shards of similar sizes, optionally shuffled and filtered
+    Reshard(ReshardCliOpts),
+}
+
+#[derive(StructOpt, Debug)]
+pub enum LabelMapCommand {
+    /// Merge the label maps of several datasets into one, and write a remapping table for each source
+    Merge(MergeCliOpts),
+    /// Convert a label map to or from classes.txt, JSON, Keras, COCO or YOLO formats
+    Convert(ConvertCliOpts),
 }
 
 #[derive(StructOpt, Debug)]
 pub enum PascalVoc {
     /// Prepare a PASCAL-VOC dataset for tensorflow
-    /// This operations generates the label map and two tfrecord files: a training set and a test set
-    Prepare(PrepareCliOpts),
+    /// This operations generates the label map and one tfrecord file per set, by default a training set and a test set
+    Prepare(Box<PrepareCliOpts>),
+    /// Find exact and near-duplicate images in a PASCAL-VOC dataset
+    Dedupe(DedupeCliOpts),
+    /// Print statistics about a PASCAL-VOC dataset: classes, boxes and image resolutions
+    Stats(StatsCliOpts),
+    /// Suggest anchor boxes by clustering the sizes of all boxes with k-means
+    Anchors(AnchorsCliOpts),
+    /// Draw boxes and class names onto images, from a dataset or a tfrecord file
+    Preview(PreviewCliOpts),
 }
 
 #[derive(StructOpt, Debug)]
 pub struct PrepareCliOpts {
+    /// TOML configuration file, options passed on the command line take precedence.
+    /// The resolved configuration is saved to prepare.toml in the output directory
+    #[structopt(long = "config")]
+    pub config: Option<PathBuf>,
+    /// Input directory, where your dataset is. Will be searched recursively
+    #[structopt(short = "i", long = "input", required_unless = "config")]
+    pub input: Option<PathBuf>,
+    /// Output directory, where the TensorFlow configuration files will be written
+    #[structopt(short = "o", long = "output", required_unless = "config")]
+    pub output: Option<PathBuf>,
+    /// Share of data that should be retained and placed in the test set: 20%, 20, 0.2 or 1/5 [default: 20%].
+    /// Whole numbers are percentages (1 is 1%), use 1.0 or 100% for the whole dataset
+    #[structopt(long = "retain")]
+    pub retain: Option<String>,
+    /// Exact number of examples placed in the test set, overrides --retain. Implies --split-mode exact,
+    /// other modes only approximate a count. Groups and duplicates count as one
+    #[structopt(long = "test-count", conflicts_with_all = &["split", "folds"])]
+    pub test_count: Option<usize>,
+    /// Named sets and their share of the data, for instance train=70,val=15,test=15. Overrides --retain
+    #[structopt(long = "split")]
+    pub split: Option<String>,
+    /// Generate K folds for cross-validation, written to fold-i/train.tfrecord and fold-i/test.tfrecord
+    #[structopt(long = "folds", conflicts_with = "split")]
+    pub folds: Option<usize>,
+    /// How examples are split: hash (per image), stratified (per class, keeps rare classes on both sides)
+    /// or exact (hits the requested counts exactly) [default: hash]
+    #[structopt(long = "split-mode")]
+    pub split_mode: Option<String>,
+    /// Directory of a split manifest (the splits directory of a previous run).
+    /// Listed examples keep their set, new examples are split as usual
+    #[structopt(long = "from-manifest")]
+    pub from_manifest: Option<PathBuf>,
+    /// Seed used to draw a different, reproducible, split
+    #[structopt(long = "seed")]
+    pub seed: Option<u64>,
+    /// Keep related images in the same set: directory, field:folder, field:path, field:database
+    /// or filename:<regex>, where the first capture group of the regex is the group key
+    #[structopt(long = "group-by")]
+    pub group_by: Option<String>,
+    /// TOML file of rules that rename, merge, ignore or whitelist classes before the label map is generated
+    #[structopt(long = "label-rules")]
+    pub label_rules: Option<PathBuf>,
+    /// Label map in protobuf text format, for instance from `label-map merge`. Classes keep their ID,
+    /// classes missing from the map are an error. By default, the label map is generated
+    #[structopt(long = "label-map")]
+    pub label_map: Option<PathBuf>,
+    /// TOML file of class definitions: display names, keypoints and hierarchy, written to the label map
+    #[structopt(long = "classes")]
+    pub classes: Option<PathBuf>,
+    /// Look for duplicate images: group keeps each cluster of duplicates in a single set,
+    /// keep-one only keeps the first example of each cluster
+    #[structopt(long = "dedupe")]
+    pub dedupe: Option<String>,
+    /// Maximum number of different bits (out of 64) between the perceptual hashes of near-duplicates [default: 4]
+    #[structopt(long = "max-distance")]
+    pub max_distance: Option<u32>,
+    /// Format used for images that are not PNG or JPEG (BMP, TIFF, WebP...), jpeg or png [default: jpeg]
+    #[structopt(long = "transcode-to")]
+    pub transcode_to: Option<String>,
+    /// Leave objects flagged as difficult out of the training sets, they are kept in other sets
+    #[structopt(long = "exclude-difficult")]
+    pub exclude_difficult: bool,
+    /// Names of the training sets for --exclude-difficult, for instance train,extra [default: train].
+    /// The training sets of folds always are
+    #[structopt(long = "training-sets")]
+    pub training_sets: Option<String>,
+    /// Keep difficult objects in the train set, when the configuration file excludes them
+    #[structopt(long = "no-exclude-difficult", conflicts_with = "exclude-difficult")]
+    pub no_exclude_difficult: bool,
+    /// Add the path and SHA-256 of the source annotation file to each example
+    #[structopt(long = "provenance")]
+    pub provenance: bool,
+    /// Don't add the source annotation file to each example, when the configuration file does
+    #[structopt(long = "no-provenance", conflicts_with = "provenance")]
+    pub no_provenance: bool,
+    /// Feature keys of the output examples: od-api, tfds-voc, keras-cv or the path to a TOML schema file
+    /// [default: od-api]
+    #[structopt(long = "schema")]
+    pub schema: Option<String>,
+    /// Number of tfrecord files written for each set, named <set>-00000-of-0000N.tfrecord [default: 1]
+    #[structopt(long = "shards")]
+    pub shards: Option<usize>,
+    /// Write a self-contained report.html with counts, class histograms, errors and sample thumbnails
+    #[structopt(long = "html-report")]
+    pub html_report: bool,
+    /// Don't write report.html, when the configuration file asks for it
+    #[structopt(long = "no-html-report", conflicts_with = "html-report")]
+    pub no_html_report: bool,
+    /// How the report is printed: text or json [default: text]
+    #[structopt(long = "report-format")]
+    pub report_format: Option<String>,
+    /// Exit with an error when annotations are invalid (exit code 2), a set is empty (3)
+    /// or examples are dropped (4), for instance invalid,empty-split,dropped. `none` clears the
+    /// conditions of the configuration file
+    #[structopt(long = "fail-on")]
+    pub fail_on: Option<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct MergeCliOpts {
+    /// Label maps to merge, in protobuf text format. Classes of the first ones keep their ID first
+    #[structopt(required = true, min_values = 2)]
+    pub inputs: Vec<PathBuf>,
+    /// Output directory, where the merged label map and the remapping tables will be written
+    #[structopt(short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ConvertCliOpts {
+    /// Label map to convert
+    pub input: PathBuf,
+    /// Converted label map
+    pub output: PathBuf,
+    /// Format of the input: pbtxt, classes, json, keras, coco or yolo. Detected from the extension
+    /// and content by default
+    #[structopt(long = "from")]
+    pub from: Option<String>,
+    /// Format of the output: pbtxt, classes, json, keras, coco or yolo
+    #[structopt(long = "to")]
+    pub to: String,
+    /// ID of the first class in lists (classes, keras and yolo), 0 is the background in the object detection API
+    #[structopt(long = "first-id", default_value = "1")]
+    pub first_id: i64,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct CheckLabelsCliOpts {
+    /// tfrecord files to check
+    #[structopt(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// Label map in protobuf text format
+    #[structopt(long = "label-map")]
+    pub label_map: PathBuf,
+    /// Feature keys of the tfrecord files: od-api, tfds-voc, keras-cv or the path to a TOML schema file
+    #[structopt(long = "schema", default_value = "od-api")]
+    pub schema: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ReshardCliOpts {
+    /// tfrecord files to read, in order
+    #[structopt(required = true)]
+    pub inputs: Vec<PathBuf>,
+    /// Output directory of the shards
+    #[structopt(short = "o", long = "output")]
+    pub output: PathBuf,
+    /// Name of the shards, written to <name>-00000-of-0000N.tfrecord
+    #[structopt(long = "name", default_value = "data")]
+    pub name: String,
+    /// Number of output shards
+    #[structopt(long = "shards", default_value = "1")]
+    pub shards: usize,
+    /// Shuffle the records, inputs are interleaved at random in proportion to their size and records mixed through a buffer
+    #[structopt(long = "shuffle")]
+    pub shuffle: bool,
+    /// Seed of the shuffle, the same seed gives the same shards
+    #[structopt(long = "seed", default_value = "0")]
+    pub seed: u64,
+    /// Number of records held in memory while shuffling, a larger buffer mixes records further apart.
+    /// Records are held whole: 10000 records with images take several GB
+    #[structopt(long = "shuffle-buffer", default_value = "10000")]
+    pub shuffle_buffer: usize,
+    /// Only keep records that satisfy the filter, repeat to combine them: "image/object/class/text contains dog",
+    /// "image/width >= 640" or "count(image/object/bbox/xmin) > 0"
+    #[structopt(long = "filter", number_of_values = 1)]
+    pub filters: Vec<String>,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct DedupeCliOpts {
     /// Input directory, where your dataset is. Will be searched recursively
     #[structopt(short = "i", long = "input")]
     pub input: PathBuf,
-    /// Output directory, where the TensorFlow configuration files will be written
+    /// Maximum number of different bits (out of 64) between the perceptual hashes of near-duplicates
+    #[structopt(long = "max-distance", default_value = "4")]
+    pub max_distance: u32,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct StatsCliOpts {
+    /// Input directory, where your dataset is. Will be searched recursively
+    #[structopt(short = "i", long = "input")]
+    pub input: PathBuf,
+    /// Output format: table or json
+    #[structopt(long = "format", default_value = "table")]
+    pub format: String,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct AnchorsCliOpts {
+    /// Input directory, where your dataset is. Will be searched recursively
+    #[structopt(short = "i", long = "input")]
+    pub input: PathBuf,
+    /// Number of anchors, usually 9 for YOLO and 3 to 6 per feature map for SSD
+    #[structopt(short = "k", long = "clusters", default_value = "9")]
+    pub clusters: usize,
+    /// Side of the square network input, anchors are also printed in pixels for this size
+    #[structopt(long = "input-size", default_value = "416")]
+    pub input_size: u32,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct PreviewCliOpts {
+    /// Input directory, where your dataset is. Will be searched recursively
+    #[structopt(short = "i", long = "input", required_unless = "tfrecord")]
+    pub input: Option<PathBuf>,
+    /// Preview the examples of a tfrecord file instead of a dataset
+    #[structopt(long = "tfrecord", conflicts_with = "input")]
+    pub tfrecord: Option<PathBuf>,
+    /// Output directory, where previews will be written
     #[structopt(short = "o", long = "output")]
     pub output: PathBuf,
-    /// Percentage of data that should be retained and placed in the test set
-    #[structopt(long = "retain", default_value = "20%")]
-    pub retain: String,
+    /// Only render N examples, the sample is the same between runs
+    #[structopt(long = "sample")]
+    pub sample: Option<usize>,
+    /// Feature keys of the tfrecord file: od-api, tfds-voc, keras-cv or the path to a TOML schema file
+    #[structopt(long = "schema", default_value = "od-api")]
+    pub schema: String,
 }
 
-// Convert the CLI structure for the prepare operation into out internal representation
-impl TryFrom<PrepareCliOpts> for PrepareOpts {
+// Convert the resolved configuration of the prepare operation into out internal representation.
+// Options that have a default value are always set once the configuration is resolved.
+impl TryFrom<PrepareConfig> for PrepareOpts {
     type Error = CliError;
 
-    fn try_from(cli: PrepareCliOpts) -> Result<PrepareOpts, CliError> {
-        let retain = if cli.retain.contains('/') {
-            cli.retain.split('/').next().unwrap_or("").to_owned()
-        } else if cli.retain.contains('%') {
-            cli.retain.split('%').next().unwrap_or("").to_owned()
+    fn try_from(config: PrepareConfig) -> Result<PrepareOpts, CliError> {
+        let split = config.split;
+        // Only the exact mode hits a count, it is the default with a test count
+        let test_count = split.test_count.filter(|_| split.sets.is_none());
+        let default_mode = if test_count.is_some() {
+            "exact"
         } else {
-            cli.retain
+            "hash"
+        };
+        let mode = parse_split_mode(split.mode.as_deref().unwrap_or(default_mode))?;
+        if test_count.is_some() && mode != SplitMode::Exact {
+            return Err(CliError::TestCountMode(split.mode.unwrap_or_default()));
+        }
+
+        let splits = match (&split.sets, split.test_count) {
+            (Some(sets), _) => parse_splits(sets)?,
+            (None, Some(count)) => vec![
+                SplitSpec::with_count("test", count),
+                SplitSpec::new("train", 1f64),
+            ],
+            (None, None) => {
+                let retain = split.retain.as_deref().unwrap_or("20%");
+                let test = parse_ratio(retain)?;
+                vec![
+                    SplitSpec::new("test", test),
+                    SplitSpec::new("train", 1f64 - test),
+                ]
+            }
         };
 
+        if let Some(folds) = split.folds {
+            if folds < 2 {
+                return Err(CliError::Folds(folds));
+            }
+        }
+
+        let examples = config.examples;
+        let report = config.report;
+        let max_distance = config.duplicates.max_distance.unwrap_or(4);
         let opts = PrepareOpts {
+            input: config.input.ok_or(CliError::MissingOption("input"))?,
+            output: config.output.ok_or(CliError::MissingOption("output"))?,
+            splits,
+            split: SplitOpts {
+                mode,
+                group_by: split.group_by.as_deref().map(parse_group_by).transpose()?,
+                seed: split.seed,
+                ..Default::default()
+            },
+            folds: split.folds,
+            from_manifest: split.from_manifest,
+            label_rules: config
+                .labels
+                .rules
+                .as_deref()
+                .map(LabelRules::from_file)
+                .transpose()?,
+            label_map: config
+                .labels
+                .label_map
+                .as_deref()
+                .map(LabelMap::from_file)
+                .transpose()?,
+            classes: config
+                .labels
+                .classes
+                .as_deref()
+                .map(ClassDefinitions::from_file)
+                .transpose()?,
+            duplicates: config
+                .duplicates
+                .handling
+                .as_deref()
+                .map(|handling| parse_duplicates(handling, max_distance))
+                .transpose()?,
+            shards: examples.shards.unwrap_or(1).max(1),
+            image_encoding: parse_image_encoding(
+                config.images.transcode_to.as_deref().unwrap_or("jpeg"),
+            )?,
+            exclude_difficult: examples.exclude_difficult.unwrap_or(false),
+            training_sets: examples
+                .training_sets
+                .unwrap_or_else(|| vec!["train".to_owned()]),
+            provenance: examples.provenance.unwrap_or(false),
+            schema: parse_schema(examples.schema.as_deref().unwrap_or("od-api"))?,
+            html_report: report.html.unwrap_or(false),
+            report_format: parse_report_format(report.format.as_deref().unwrap_or("text"))?,
+            fail_on: report
+                .fail_on
+                .unwrap_or_default()
+                .iter()
+                .map(|condition| parse_fail_on(condition))
+                .collect::<Result<_, _>>()?,
+            // Turned on by main, from the global --quiet flag
+            progress: false,
+        };
+
+        Ok(opts)
+    }
+}
+
+impl From<MergeCliOpts> for MergeOpts {
+    fn from(cli: MergeCliOpts) -> Self {
+        MergeOpts {
+            inputs: cli.inputs,
+            output: cli.output,
+        }
+    }
+}
+
+impl TryFrom<ConvertCliOpts> for ConvertOpts {
+    type Error = CliError;
+
+    fn try_from(cli: ConvertCliOpts) -> Result<Self, CliError> {
+        Ok(ConvertOpts {
             input: cli.input,
             output: cli.output,
-            test_set_ratio: u8::from_str(&retain)?,
+            from: cli
+                .from
+                .as_deref()
+                .map(parse_label_map_format)
+                .transpose()?,
+            to: parse_label_map_format(&cli.to)?,
+            first_id: cli.first_id,
+        })
+    }
+}
+
+impl TryFrom<CheckLabelsCliOpts> for CheckLabelsOpts {
+    type Error = CliError;
+
+    fn try_from(cli: CheckLabelsCliOpts) -> Result<Self, CliError> {
+        Ok(CheckLabelsOpts {
+            inputs: cli.inputs,
+            label_map: LabelMap::from_file(&cli.label_map)?,
+            schema: parse_schema(&cli.schema)?,
+        })
+    }
+}
+
+impl TryFrom<ReshardCliOpts> for ReshardOpts {
+    type Error = CliError;
+
+    fn try_from(cli: ReshardCliOpts) -> Result<Self, CliError> {
+        if cli.shards == 0 {
+            return Err(CliError::Shards);
+        }
+        let shuffle = if cli.shuffle {
+            Some(Shuffle {
+                seed: cli.seed,
+                buffer: cli.shuffle_buffer,
+            })
+        } else {
+            None
         };
 
-        Ok(opts)
+        Ok(ReshardOpts {
+            inputs: cli.inputs,
+            output: cli.output,
+            name: cli.name,
+            shards: cli.shards,
+            shuffle,
+            filters: cli
+                .filters
+                .iter()
+                .map(|filter| filter.parse())
+                .collect::<Result<_, _>>()?,
+            // Turned on by main, from the global --quiet flag
+            progress: false,
+        })
+    }
+}
+
+impl From<DedupeCliOpts> for DedupeOpts {
+    fn from(cli: DedupeCliOpts) -> Self {
+        DedupeOpts {
+            input: cli.input,
+            max_distance: cli.max_distance,
+        }
+    }
+}
+
+impl TryFrom<StatsCliOpts> for StatsOpts {
+    type Error = CliError;
+
+    fn try_from(cli: StatsCliOpts) -> Result<Self, Self::Error> {
+        let format = match cli.format.to_lowercase().as_ref() {
+            "table" => StatsFormat::Table,
+            "json" => StatsFormat::Json,
+            _ => return Err(CliError::Format(cli.format)),
+        };
+
+        Ok(StatsOpts {
+            input: cli.input,
+            format,
+        })
+    }
+}
+
+impl TryFrom<AnchorsCliOpts> for AnchorsOpts {
+    type Error = CliError;
+
+    fn try_from(cli: AnchorsCliOpts) -> Result<Self, Self::Error> {
+        if cli.clusters == 0 {
+            return Err(CliError::Clusters);
+        }
+
+        Ok(AnchorsOpts {
+            input: cli.input,
+            clusters: cli.clusters,
+            input_size: cli.input_size,
+        })
+    }
+}
+
+impl TryFrom<PreviewCliOpts> for PreviewOpts {
+    type Error = CliError;
+
+    fn try_from(cli: PreviewCliOpts) -> Result<Self, Self::Error> {
+        let source = match (cli.tfrecord, cli.input) {
+            (Some(path), _) => PreviewSource::TfRecord(path, Box::new(parse_schema(&cli.schema)?)),
+            (None, Some(input)) => PreviewSource::Dataset(input),
+            (None, None) => return Err(CliError::PreviewSource),
+        };
+
+        Ok(PreviewOpts {
+            source,
+            output: cli.output,
+            sample: cli.sample,
+        })
+    }
+}
+
+fn parse_image_encoding(input: &str) -> Result<ImageEncoding, CliError> {
+    match input.to_lowercase().as_ref() {
+        "jpeg" | "jpg" => Ok(ImageEncoding::Jpeg),
+        "png" => Ok(ImageEncoding::Png),
+        _ => Err(CliError::ImageEncoding(input.to_owned())),
+    }
+}
+
+// Parse a list of named sets, for instance train=70,val=15,test=15
+fn parse_splits(input: &str) -> Result<Vec<SplitSpec>, CliError> {
+    let mut splits: Vec<SplitSpec> = Vec::new();
+    for part in input.split(',') {
+        let mut parts = part.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let ratio = parts
+            .next()
+            .ok_or_else(|| CliError::Split(part.to_owned()))?;
+
+        let invalid_name = name.is_empty() || name.contains(path::is_separator);
+        if invalid_name || splits.iter().any(|s| s.name == name) {
+            return Err(CliError::Split(part.to_owned()));
+        }
+
+        splits.push(SplitSpec::new(name, parse_weight(ratio)?));
+    }
+
+    if splits.iter().all(|s| s.ratio == 0f64) {
+        return Err(CliError::Split(input.to_owned()));
+    }
+
+    Ok(splits)
+}
+
+// Parse a share of the dataset, given as a percentage (20% or 20), a decimal (0.2) or a fraction
+// (1/5). Whole numbers and decimals above 1 are percentages: "1" is 1%, as when --retain only took
+// percentages, and "1.0" the whole dataset. Shares above 100% are rejected.
+fn parse_ratio(input: &str) -> Result<f64, CliError> {
+    let ratio = match parse_share(input)? {
+        (value, true) if value > 1f64 || !input.contains('.') => value / 100f64,
+        (value, _) => value,
+    };
+    if ratio > 1f64 {
+        return Err(CliError::Ratio(input.to_owned()));
+    }
+
+    Ok(ratio)
+}
+
+// Parse the share of a named set. Shares are relative to each other, bare numbers are taken as
+// they are: train=4,test=1 and train=80,test=20 are the same split.
+fn parse_weight(input: &str) -> Result<f64, CliError> {
+    parse_share(input).map(|(value, _)| value)
+}
+
+// Value of a percentage, a fraction or a bare number, and whether the number was bare
+fn parse_share(input: &str) -> Result<(f64, bool), CliError> {
+    let input = input.trim();
+    let number = |value: &str| match f64::from_str(value.trim()) {
+        Ok(value) if value.is_finite() && value >= 0f64 => Ok(value),
+        _ => Err(CliError::Ratio(input.to_owned())),
+    };
+
+    if input.ends_with('%') {
+        Ok((number(input.trim_end_matches('%'))? / 100f64, false))
+    } else if input.contains('/') {
+        let mut parts = input.splitn(2, '/');
+        let numerator = number(parts.next().unwrap_or(""))?;
+        let denominator = number(parts.next().unwrap_or(""))?;
+        if denominator == 0f64 {
+            return Err(CliError::Ratio(input.to_owned()));
+        }
+        Ok((numerator / denominator, false))
+    } else {
+        Ok((number(input)?, true))
+    }
+}
+
+fn parse_duplicates(handling: &str, max_distance: u32) -> Result<DuplicateOpts, CliError> {
+    let handling = match handling.to_lowercase().as_ref() {
+        "group" => DuplicateHandling::Group,
+        "keep-one" => DuplicateHandling::KeepOne,
+        _ => return Err(CliError::Dedupe(handling.to_owned())),
+    };
+
+    Ok(DuplicateOpts {
+        handling,
+        max_distance,
+    })
+}
+
+fn parse_report_format(input: &str) -> Result<ReportFormat, CliError> {
+    match input.to_lowercase().as_ref() {
+        "text" => Ok(ReportFormat::Text),
+        "json" => Ok(ReportFormat::Json),
+        _ => Err(CliError::ReportFormat(input.to_owned())),
+    }
+}
+
+fn parse_fail_on(input: &str) -> Result<FailOn, CliError> {
+    match input.trim().to_lowercase().as_ref() {
+        "invalid" => Ok(FailOn::Invalid),
+        "empty-split" => Ok(FailOn::EmptySplit),
+        "dropped" => Ok(FailOn::Dropped),
+        _ => Err(CliError::FailOn(input.to_owned())),
+    }
+}
+
+fn parse_label_map_format(input: &str) -> Result<LabelMapFormat, CliError> {
+    match input.trim().to_lowercase().as_str() {
+        "pbtxt" => Ok(LabelMapFormat::Pbtxt),
+        "classes" | "txt" => Ok(LabelMapFormat::Classes),
+        "json" => Ok(LabelMapFormat::Json),
+        "keras" => Ok(LabelMapFormat::Keras),
+        "coco" => Ok(LabelMapFormat::Coco),
+        "yolo" => Ok(LabelMapFormat::Yolo),
+        _ => Err(CliError::LabelMapFormat(input.to_owned())),
+    }
+}
+
+fn parse_split_mode(input: &str) -> Result<SplitMode, CliError> {
+    match input.to_lowercase().as_ref() {
+        "hash" => Ok(SplitMode::Hash),
+        "stratified" => Ok(SplitMode::Stratified),
+        "exact" => Ok(SplitMode::Exact),
+        _ => Err(CliError::SplitMode(input.to_owned())),
+    }
+}
+
+fn parse_group_by(input: &str) -> Result<GroupBy, CliError> {
+    let mut parts = input.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("directory"), None) => Ok(GroupBy::Directory),
+        (Some("filename"), Some(regex)) => Ok(GroupBy::Filename(Regex::new(regex)?)),
+        (Some("field"), Some("folder")) => Ok(GroupBy::Field(AnnotationField::Folder)),
+        (Some("field"), Some("path")) => Ok(GroupBy::Field(AnnotationField::Path)),
+        (Some("field"), Some("database")) => Ok(GroupBy::Field(AnnotationField::Database)),
+        _ => Err(CliError::GroupBy(input.to_owned())),
+    }
+}
+
+// A schema is either one of the built-in schemas or a path to a custom one
+fn parse_schema(input: &str) -> Result<Schema, CliError> {
+    match Schema::from_name(input) {
+        Some(schema) => Ok(schema),
+        None => Ok(Schema::from_file(&PathBuf::from(input))?),
     }
 }
 
 #[derive(Debug, Error)]
 pub enum CliError {
-    #[error("Could not parse integer value")]
-    Integer(#[from] ParseIntError),
+    #[error("Unsupported image format {0:?}, expected jpeg or png")]
+    ImageEncoding(String),
+
+    #[error(
+        "Invalid split {0:?}, expected a list of unique names and ratios such as train=80,test=20"
+    )]
+    Split(String),
+
+    #[error("Invalid ratio {0:?}")]
+    Ratio(String),
+
+    #[error("Cross-validation needs at least 2 folds, got {0}")]
+    Folds(usize),
+
+    #[error("Unknown split mode {0:?}, expected hash, stratified or exact")]
+    SplitMode(String),
+
+    #[error("A test count needs the exact split mode, got {0:?}")]
+    TestCountMode(String),
+
+    #[error("Unknown duplicate handling {0:?}, expected group or keep-one")]
+    Dedupe(String),
+
+    #[error("At least one anchor is needed")]
+    Clusters,
+
+    #[error("Nothing to preview, expected an input directory or a tfrecord file")]
+    PreviewSource,
+
+    #[error("Unknown output format {0:?}, expected table or json")]
+    Format(String),
+
+    #[error("Unknown label map format {0:?}, expected pbtxt, classes, json, keras, coco or yolo")]
+    LabelMapFormat(String),
+
+    #[error("Unknown report format {0:?}, expected text or json")]
+    ReportFormat(String),
+
+    #[error("Unknown failure condition {0:?}, expected invalid, empty-split or dropped")]
+    FailOn(String),
+
+    #[error("Invalid grouping {0:?}, expected directory, field:<name> or filename:<regex>")]
+    GroupBy(String),
+
+    #[error("Missing option {0:?}, pass it on the command line or in the configuration file")]
+    MissingOption(&'static str),
+
+    #[error("Invalid regex")]
+    Regex(#[from] RegexError),
+
+    #[error("Could not load schema")]
+    Schema(#[from] SchemaError),
+
+    #[error("Could not load label rules")]
+    LabelRules(#[from] LabelRulesError),
+
+    #[error("Could not load label map")]
+    LabelMap(#[from] LabelMapError),
+
+    #[error("The number of shards must be at least 1")]
+    Shards,
+
+    #[error("Invalid filter")]
+    Predicate(#[from] PredicateError),
+}
+
+#[test]
+fn test_parse_ratio() {
+    assert_eq!(parse_ratio("20%").unwrap(), 0.2);
+    assert_eq!(parse_ratio("20").unwrap(), 0.2);
+    assert_eq!(parse_ratio("0.2").unwrap(), 0.2);
+    assert_eq!(parse_ratio("1/5").unwrap(), 0.2);
+    assert_eq!(parse_ratio(" 1 / 4 ").unwrap(), 0.25);
+    assert_eq!(parse_ratio("1.0").unwrap(), 1.0);
+    assert_eq!(parse_ratio("1").unwrap(), 0.01);
+    assert_eq!(parse_ratio("0").unwrap(), 0.0);
+    assert_eq!(parse_ratio("20.5").unwrap(), 0.205);
+    assert!(parse_ratio("150").is_err());
+    assert!(parse_ratio("101%").is_err());
+    assert!(parse_ratio("3/2").is_err());
+    assert!(parse_ratio("1/0").is_err());
+    assert!(parse_ratio("-20%").is_err());
+    assert!(parse_ratio("twenty").is_err());
+}
+
+#[test]
+fn test_parse_splits() {
+    let splits = parse_splits("train=4, test=1").unwrap();
+    assert_eq!((splits[0].ratio, splits[1].ratio), (4.0, 1.0));
+    let splits = parse_splits("train=99,test=1").unwrap();
+    assert_eq!((splits[0].ratio, splits[1].ratio), (99.0, 1.0));
+    assert!(parse_splits("train=80,train=20").is_err());
+    assert!(parse_splits("train=0,test=0").is_err());
+}
+
+#[test]
+fn test_parse_fail_on() {
+    assert_eq!(parse_fail_on("invalid").unwrap(), FailOn::Invalid);
+    assert_eq!(parse_fail_on(" Empty-Split").unwrap(), FailOn::EmptySplit);
+    assert_eq!(parse_fail_on("dropped").unwrap(), FailOn::Dropped);
+    assert!(parse_fail_on("empty").is_err());
+}
+
+#[test]
+fn test_test_count() {
+    let directory = std::env::temp_dir().join("tftools-test-test-count");
+    let output = directory.to_string_lossy();
+    let opts = |args: &[&str]| {
+        let mut argv = vec!["prepare", "-i", "./dataset", "-o", &output];
+        argv.extend_from_slice(args);
+        let config = PrepareConfig::from_cli(PrepareCliOpts::from_iter(&argv)).unwrap();
+        PrepareOpts::try_from(config)
+    };
+
+    // The test set gets exactly the count under the default mode
+    let prepared = opts(&["--test-count", "2"]).unwrap();
+    assert_eq!(prepared.split.mode, SplitMode::Exact);
+    let report = tftools::pascal_voc::prepare(prepared).unwrap();
+    assert_eq!(report.splits[0].name, "test");
+    assert_eq!(report.splits[0].examples, 2);
+    assert_eq!(report.splits[1].examples, 4);
+
+    assert!(matches!(
+        opts(&["--test-count", "2", "--split-mode", "hash"]),
+        Err(CliError::TestCountMode(_))
+    ));
+    std::fs::remove_dir_all(&directory).unwrap();
 }
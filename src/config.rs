@@ -0,0 +1,363 @@
+//! Declarative configuration of `prepare`, read from a TOML file passed with `--config`.
+//! Options passed on the command line take precedence over the file. Relative paths of the file
+//! are resolved against its directory, so that the file can be checked in next to the dataset.
+//! The resolved configuration is saved next to the outputs, with absolute paths.
+use std::env;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::cli::PrepareCliOpts;
+use tftools::pascal_voc::Schema;
+
+/// Name of the resolved configuration, written to the output directory
+pub const RESOLVED_CONFIG: &str = "prepare.toml";
+
+/// Options of `prepare`, all of them are optional in the file
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrepareConfig {
+    /// Input directory, searched recursively. A single one: split manifests identify examples
+    /// by their path relative to it.
+    pub input: Option<PathBuf>,
+    /// Output directory
+    pub output: Option<PathBuf>,
+    pub split: SplitConfig,
+    pub labels: LabelsConfig,
+    pub duplicates: DuplicatesConfig,
+    pub images: ImagesConfig,
+    pub examples: ExamplesConfig,
+    pub report: ReportConfig,
+}
+
+/// How the dataset is split, `sets`, `test_count` and `retain` are exclusive, in this order.
+/// `folds` replace `retain`, and can't be used with `sets` or `test_count`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SplitConfig {
+    /// Share of the test set: 20%, 20, 0.2 or 1/5
+    pub retain: Option<String>,
+    /// Exact number of examples in the test set, the mode is exact
+    pub test_count: Option<usize>,
+    /// Named sets and their share, for instance train=70,val=15,test=15
+    pub sets: Option<String>,
+    /// Number of folds for cross-validation, replaces the sets
+    pub folds: Option<usize>,
+    /// hash, stratified or exact
+    pub mode: Option<String>,
+    pub seed: Option<u64>,
+    /// directory, field:<name> or filename:<regex>
+    pub group_by: Option<String>,
+    /// Directory of a split manifest from a previous run
+    pub from_manifest: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelsConfig {
+    /// TOML file of rules applied to class names
+    pub rules: Option<PathBuf>,
+    /// Label map used instead of generating one
+    pub label_map: Option<PathBuf>,
+    /// TOML file of class definitions
+    pub classes: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuplicatesConfig {
+    /// group or keep-one, duplicates are not searched when missing
+    pub handling: Option<String>,
+    pub max_distance: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImagesConfig {
+    /// jpeg or png, for images that are not PNG or JPEG
+    pub transcode_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExamplesConfig {
+    /// od-api, tfds-voc, keras-cv or the path to a TOML schema file
+    pub schema: Option<String>,
+    /// Number of tfrecord files for each set
+    pub shards: Option<usize>,
+    pub exclude_difficult: Option<bool>,
+    /// Sets difficult objects are excluded from
+    pub training_sets: Option<Vec<String>>,
+    pub provenance: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportConfig {
+    /// Write report.html
+    pub html: Option<bool>,
+    /// text or json
+    pub format: Option<String>,
+    /// invalid, empty-split or dropped
+    pub fail_on: Option<Vec<String>>,
+}
+
+impl SplitConfig {
+    // Same conflicts as the options of the command line, the file can't pick one of them
+    fn check_conflicts(&self) -> Result<(), ConfigError> {
+        let conflicts = [
+            ("sets", self.sets.is_some(), "folds", self.folds.is_some()),
+            (
+                "test_count",
+                self.test_count.is_some(),
+                "folds",
+                self.folds.is_some(),
+            ),
+            (
+                "sets",
+                self.sets.is_some(),
+                "test_count",
+                self.test_count.is_some(),
+            ),
+        ];
+        match conflicts
+            .iter()
+            .find(|(_, first, _, second)| *first && *second)
+        {
+            Some((first, _, second, _)) => Err(ConfigError::Conflict(first, second)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl PrepareConfig {
+    /// Load a configuration file, relative paths are resolved against its directory
+    pub fn from_file(path: &Path) -> Result<PrepareConfig, ConfigError> {
+        let content = fs::read_to_string(path)?;
+        let mut config: PrepareConfig = toml::from_str(&content)?;
+        config.split.check_conflicts()?;
+
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        config.map_paths(|path| directory.join(path));
+
+        Ok(config)
+    }
+
+    /// Configuration of the command line: the file passed with `--config`, if any, overridden
+    /// by the options on the command line. Missing options get their default value.
+    pub fn from_cli(cli: PrepareCliOpts) -> Result<PrepareConfig, ConfigError> {
+        let config = match &cli.config {
+            Some(path) => PrepareConfig::from_file(path)?,
+            None => PrepareConfig::default(),
+        };
+
+        Ok(config.merge(cli).with_defaults())
+    }
+
+    /// Write the configuration to the output directory, paths are made absolute
+    pub fn save(&self, directory: &Path) -> Result<PathBuf, ConfigError> {
+        let mut config = self.clone();
+        let current_dir = env::current_dir()?;
+        config.map_paths(|path| current_dir.join(path));
+
+        let path = directory.join(RESOLVED_CONFIG);
+        fs::write(&path, toml::to_string_pretty(&config)?)?;
+
+        Ok(path)
+    }
+
+    // Options of the command line replace the ones of the file. Setting any option of the
+    // split definition replaces the whole definition.
+    fn merge(mut self, cli: PrepareCliOpts) -> PrepareConfig {
+        let split = &mut self.split;
+        if cli.retain.is_some() || cli.test_count.is_some() || cli.split.is_some() {
+            split.retain = cli.retain;
+            split.test_count = cli.test_count;
+            split.sets = cli.split;
+            split.folds = None;
+        }
+        if cli.folds.is_some() {
+            split.folds = cli.folds;
+            split.sets = None;
+        }
+        override_with(&mut split.mode, cli.split_mode);
+        override_with(&mut split.seed, cli.seed);
+        override_with(&mut split.group_by, cli.group_by);
+        override_with(&mut split.from_manifest, cli.from_manifest);
+
+        override_with(&mut self.input, cli.input);
+        override_with(&mut self.output, cli.output);
+        override_with(&mut self.labels.rules, cli.label_rules);
+        override_with(&mut self.labels.label_map, cli.label_map);
+        override_with(&mut self.labels.classes, cli.classes);
+        override_with(&mut self.duplicates.handling, cli.dedupe);
+        override_with(&mut self.duplicates.max_distance, cli.max_distance);
+        override_with(&mut self.images.transcode_to, cli.transcode_to);
+
+        let examples = &mut self.examples;
+        override_with(&mut examples.schema, cli.schema);
+        override_with(&mut examples.shards, cli.shards);
+        override_flag(
+            &mut examples.exclude_difficult,
+            cli.exclude_difficult,
+            cli.no_exclude_difficult,
+        );
+        let training_sets = cli
+            .training_sets
+            .map(|sets| sets.split(',').map(|s| s.trim().to_owned()).collect());
+        override_with(&mut examples.training_sets, training_sets);
+        override_flag(&mut examples.provenance, cli.provenance, cli.no_provenance);
+
+        let report = &mut self.report;
+        override_flag(&mut report.html, cli.html_report, cli.no_html_report);
+        override_with(&mut report.format, cli.report_format);
+        let fail_on = cli.fail_on.map(|f| match f.trim() {
+            "none" => Vec::new(),
+            f => f.split(',').map(|c| c.trim().to_owned()).collect(),
+        });
+        override_with(&mut report.fail_on, fail_on);
+
+        self
+    }
+
+    // Fill missing options with the defaults of the command line
+    fn with_defaults(mut self) -> PrepareConfig {
+        let split = &mut self.split;
+        if split.sets.is_none() && split.test_count.is_none() && split.folds.is_none() {
+            split.retain.get_or_insert_with(|| "20%".to_owned());
+        }
+        let mode = match (&split.sets, split.test_count) {
+            (None, Some(_)) => "exact",
+            _ => "hash",
+        };
+        split.mode.get_or_insert_with(|| mode.to_owned());
+        self.duplicates.max_distance.get_or_insert(4);
+        self.images
+            .transcode_to
+            .get_or_insert_with(|| "jpeg".to_owned());
+
+        let examples = &mut self.examples;
+        examples.schema.get_or_insert_with(|| "od-api".to_owned());
+        examples.shards.get_or_insert(1);
+        examples.exclude_difficult.get_or_insert(false);
+        examples
+            .training_sets
+            .get_or_insert_with(|| vec!["train".to_owned()]);
+        examples.provenance.get_or_insert(false);
+
+        let report = &mut self.report;
+        report.html.get_or_insert(false);
+        report.format.get_or_insert_with(|| "text".to_owned());
+        report.fail_on.get_or_insert_with(Vec::new);
+
+        self
+    }
+
+    // Apply a function to every relative path: directories, label files and schema files
+    fn map_paths<F>(&mut self, f: F)
+    where
+        F: Fn(&Path) -> PathBuf,
+    {
+        let relative = |path: &mut PathBuf| {
+            if path.is_relative() {
+                *path = f(path);
+            }
+        };
+        self.input.iter_mut().for_each(relative);
+        self.output.iter_mut().for_each(relative);
+        self.split.from_manifest.iter_mut().for_each(relative);
+        self.labels.rules.iter_mut().for_each(relative);
+        self.labels.label_map.iter_mut().for_each(relative);
+        self.labels.classes.iter_mut().for_each(relative);
+
+        // Names of built-in schemas are not paths
+        if let Some(schema) = &mut self.examples.schema {
+            if Schema::from_name(schema).is_none() && Path::new(schema).is_relative() {
+                *schema = f(Path::new(schema)).to_string_lossy().into_owned();
+            }
+        }
+    }
+}
+
+// Replace the option when a value was passed on the command line
+fn override_with<T>(option: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *option = value;
+    }
+}
+
+// Flags of the command line come in pairs, `--flag` turns the option on and `--no-flag` off
+fn override_flag(option: &mut Option<bool>, on: bool, off: bool) {
+    if on {
+        *option = Some(true);
+    } else if off {
+        *option = Some(false);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Could not read or write the configuration file")]
+    Io(#[from] IoError),
+
+    #[error("Invalid configuration file")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("Could not serialize the configuration")]
+    Serialize(#[from] toml::ser::Error),
+
+    #[error("Options split.{0} and split.{1} can't be used together")]
+    Conflict(&'static str, &'static str),
+}
+
+#[test]
+fn test_merge() {
+    use structopt::StructOpt;
+
+    let file = PrepareConfig::from_file(Path::new("./dataset/prepare.toml")).unwrap();
+    assert_eq!(file.input, Some(PathBuf::from("./dataset/.")));
+    assert_eq!(file.split.sets.as_deref(), Some("train=80,test=20"));
+    assert_eq!(file.report.fail_on, Some(vec!["invalid".to_owned()]));
+
+    let cli = PrepareCliOpts::from_iter(&[
+        "prepare",
+        "--config",
+        "./dataset/prepare.toml",
+        "--retain",
+        "10%",
+        "--provenance",
+    ]);
+    let config = PrepareConfig::from_cli(cli).unwrap();
+    assert_eq!(config.split.retain.as_deref(), Some("10%"));
+    assert_eq!(config.split.sets, None);
+    assert_eq!(config.split.seed, Some(42));
+    assert_eq!(config.examples.provenance, Some(true));
+    assert_eq!(config.examples.shards, Some(2));
+    assert_eq!(config.images.transcode_to.as_deref(), Some("jpeg"));
+    assert_eq!(config.report.html, Some(true));
+
+    // Options of the file can be turned off
+    let cli = PrepareCliOpts::from_iter(&[
+        "prepare",
+        "--config",
+        "./dataset/prepare.toml",
+        "--no-html-report",
+        "--fail-on",
+        "none",
+    ]);
+    let config = PrepareConfig::from_cli(cli).unwrap();
+    assert_eq!(config.report.html, Some(false));
+    assert_eq!(config.report.fail_on, Some(Vec::new()));
+
+    // The file can't pick between exclusive options
+    let path = std::env::temp_dir().join("tftools-test-conflict.toml");
+    fs::write(&path, "[split]\nsets = \"train=80,test=20\"\nfolds = 5\n").unwrap();
+    assert!(matches!(
+        PrepareConfig::from_file(&path),
+        Err(ConfigError::Conflict("sets", "folds"))
+    ));
+    fs::remove_file(&path).unwrap();
+}
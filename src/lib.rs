@@ -0,0 +1,7 @@
+//! Tools to prepare datasets for TensorFlow.
+//! The `tftools` binary is built on top of this library, which can be used on its own to
+//! read PASCAL-VOC datasets, split them and write tfrecord files.
+pub mod math;
+pub mod pascal_voc;
+mod progress;
+mod tensorflow_protos;
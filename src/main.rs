@@ -1,24 +1,122 @@
 mod cli;
-mod math;
-mod pascal_voc;
-mod tensorflow_protos;
+mod config;
 
 use std::convert::TryFrom;
 use std::error::Error;
+use std::process;
 
+use log::{info, LevelFilter};
 use structopt::StructOpt;
 
-use cli::{Command, PascalVoc};
-use pascal_voc::{prepare, PrepareOpts};
+use cli::{Cli, Command, LabelMapCommand, PascalVoc, TfRecordCommand};
+use config::PrepareConfig;
+use tftools::pascal_voc::{
+    anchors, check_labels, convert, dedupe, merge_label_maps, prepare, preview, reshard, stats,
+    AnchorsOpts, CheckLabelsOpts, ConvertOpts, DedupeOpts, MergeOpts, PrepareOpts, PreviewOpts,
+    ReshardOpts, StatsOpts,
+};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    match Command::from_args() {
+    let cli = Cli::from_args();
+    init_logger(cli.log_level());
+
+    match cli.command {
         // PASCAL-VOC commands
         Command::PascalVoc(pv_cmd) => match pv_cmd {
             // Prepare subcommand
             PascalVoc::Prepare(opts) => {
-                let opts = PrepareOpts::try_from(opts)?;
+                let config = PrepareConfig::from_cli(*opts)?;
+                let opts = PrepareOpts {
+                    progress: !cli.quiet,
+                    ..PrepareOpts::try_from(config.clone())?
+                };
+                let output = opts.output.clone();
+                let format = opts.report_format;
+                let fail_on = opts.fail_on.clone();
                 let report = prepare(opts)?;
+
+                // Save the resolved configuration next to the outputs
+                let saved = config.save(&output)?;
+                info!("Resolved configuration written to {:?}", saved);
+                report.print_report(format)?;
+
+                // Data quality issues, the first condition met gives the exit code
+                if let Some(failure) = report.failures(&fail_on).first() {
+                    eprintln!("Error: {}", failure);
+                    process::exit(failure.exit_code());
+                }
+
+                Ok(())
+            }
+            // Dedupe subcommand
+            PascalVoc::Dedupe(opts) => {
+                let report = dedupe(DedupeOpts::from(opts));
+                report.print_report();
+
+                Ok(())
+            }
+            // Anchors subcommand
+            PascalVoc::Anchors(opts) => {
+                let report = anchors(AnchorsOpts::try_from(opts)?);
+                report.print_report();
+
+                Ok(())
+            }
+            // Preview subcommand
+            PascalVoc::Preview(opts) => {
+                let report = preview(PreviewOpts::try_from(opts)?)?;
+                report.print_report();
+
+                Ok(())
+            }
+            // Stats subcommand
+            PascalVoc::Stats(opts) => {
+                let opts = StatsOpts::try_from(opts)?;
+                let format = opts.format;
+                let report = stats(opts);
+                report.print_report(format)?;
+
+                Ok(())
+            }
+        },
+        // Label map commands
+        Command::LabelMap(lm_cmd) => match lm_cmd {
+            // Merge subcommand
+            LabelMapCommand::Merge(opts) => {
+                let report = merge_label_maps(MergeOpts::from(opts))?;
+                report.print_report();
+
+                Ok(())
+            }
+            // Convert subcommand
+            LabelMapCommand::Convert(opts) => {
+                let report = convert(ConvertOpts::try_from(opts)?)?;
+                report.print_report();
+
+                Ok(())
+            }
+        },
+        // tfrecord commands
+        Command::TfRecord(tf_cmd) => match tf_cmd {
+            // Check labels subcommand
+            TfRecordCommand::CheckLabels(opts) => {
+                let report = check_labels(CheckLabelsOpts::try_from(opts)?)?;
+                report.print_report();
+
+                if !report.is_consistent() {
+                    eprintln!("Error: the records don't match the label map");
+                    process::exit(2);
+                }
+
+                Ok(())
+            }
+            // Reshard subcommand
+            TfRecordCommand::Reshard(opts) => {
+                let opts = ReshardOpts {
+                    progress: !cli.quiet,
+                    ..ReshardOpts::try_from(opts)?
+                };
+                let report = reshard(opts)?;
                 report.print_report();
 
                 Ok(())
@@ -26,3 +124,12 @@ fn main() -> Result<(), Box<dyn Error>> {
         },
     }
 }
+
+// Log messages of tftools to stderr, dependencies only log warnings and errors
+fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter(None, level.min(LevelFilter::Warn))
+        .filter(Some("tftools"), level)
+        .format_timestamp(None)
+        .init();
+}
@@ -0,0 +1,213 @@
+//! Bounding box geometry. Boxes are stored as corners, in pixels or normalized by the image
+//! size, and converted from and to the layouts used by training frameworks.
+//! The y axis points down, as in images: `ymin` is the top of the box.
+
+/// An axis-aligned bounding box. Coordinates are either in pixels or normalized by the image
+/// size, the box doesn't keep track of which one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BBox {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+impl BBox {
+    /// Box from its corners: xmin, ymin, xmax, ymax
+    pub fn from_xyxy(xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> BBox {
+        BBox {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        }
+    }
+
+    /// Box from its corners, y first: ymin, xmin, ymax, xmax
+    pub fn from_yxyx(ymin: f64, xmin: f64, ymax: f64, xmax: f64) -> BBox {
+        BBox::from_xyxy(xmin, ymin, xmax, ymax)
+    }
+
+    /// Box from its top left corner and its size
+    pub fn from_xywh(xmin: f64, ymin: f64, width: f64, height: f64) -> BBox {
+        BBox::from_xyxy(xmin, ymin, xmin + width, ymin + height)
+    }
+
+    /// Box from its center and its size
+    pub fn from_cxcywh(cx: f64, cy: f64, width: f64, height: f64) -> BBox {
+        BBox::from_xywh(cx - width / 2.0, cy - height / 2.0, width, height)
+    }
+
+    pub fn to_xyxy(&self) -> [f64; 4] {
+        [self.xmin, self.ymin, self.xmax, self.ymax]
+    }
+
+    pub fn to_yxyx(&self) -> [f64; 4] {
+        [self.ymin, self.xmin, self.ymax, self.xmax]
+    }
+
+    pub fn to_xywh(&self) -> [f64; 4] {
+        [self.xmin, self.ymin, self.width(), self.height()]
+    }
+
+    pub fn to_cxcywh(&self) -> [f64; 4] {
+        let (cx, cy) = self.center();
+        [cx, cy, self.width(), self.height()]
+    }
+
+    /// Width, 0 for inverted boxes
+    pub fn width(&self) -> f64 {
+        (self.xmax - self.xmin).max(0.0)
+    }
+
+    /// Height, 0 for inverted boxes
+    pub fn height(&self) -> f64 {
+        (self.ymax - self.ymin).max(0.0)
+    }
+
+    pub fn area(&self) -> f64 {
+        self.width() * self.height()
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        ((self.xmin + self.xmax) / 2.0, (self.ymin + self.ymax) / 2.0)
+    }
+
+    /// Width divided by height, 0 for empty boxes
+    pub fn aspect_ratio(&self) -> f64 {
+        if self.height() > 0.0 {
+            self.width() / self.height()
+        } else {
+            0.0
+        }
+    }
+
+    /// Convert coordinates in pixels to coordinates between 0 and 1
+    pub fn normalize(&self, width: f64, height: f64) -> BBox {
+        BBox::from_xyxy(
+            self.xmin / width,
+            self.ymin / height,
+            self.xmax / width,
+            self.ymax / height,
+        )
+    }
+
+    /// Convert coordinates between 0 and 1 to coordinates in pixels
+    pub fn denormalize(&self, width: f64, height: f64) -> BBox {
+        self.scale(width, height)
+    }
+
+    /// Multiply x coordinates by `sx` and y coordinates by `sy`, for instance after resizing
+    /// the image
+    pub fn scale(&self, sx: f64, sy: f64) -> BBox {
+        BBox::from_xyxy(
+            self.xmin * sx,
+            self.ymin * sy,
+            self.xmax * sx,
+            self.ymax * sy,
+        )
+    }
+
+    /// Round coordinates to the nearest integer, usually to get pixels
+    pub fn round(&self) -> BBox {
+        BBox::from_xyxy(
+            self.xmin.round(),
+            self.ymin.round(),
+            self.xmax.round(),
+            self.ymax.round(),
+        )
+    }
+
+    /// Keep the box within an image of the given size. Use 1 and 1 for normalized boxes.
+    pub fn clip(&self, width: f64, height: f64) -> BBox {
+        BBox::from_xyxy(
+            self.xmin.clamp(0.0, width),
+            self.ymin.clamp(0.0, height),
+            self.xmax.clamp(0.0, width),
+            self.ymax.clamp(0.0, height),
+        )
+    }
+
+    /// Mirror the box, as if the image of the given width was flipped left to right
+    pub fn flip_horizontal(&self, width: f64) -> BBox {
+        BBox::from_xyxy(width - self.xmax, self.ymin, width - self.xmin, self.ymax)
+    }
+
+    /// Mirror the box, as if the image of the given height was flipped upside down
+    pub fn flip_vertical(&self, height: f64) -> BBox {
+        BBox::from_xyxy(self.xmin, height - self.ymax, self.xmax, height - self.ymin)
+    }
+
+    /// Overlapping area of two boxes, None if they don't overlap
+    pub fn intersection(&self, other: &BBox) -> Option<BBox> {
+        let intersection = BBox::from_xyxy(
+            self.xmin.max(other.xmin),
+            self.ymin.max(other.ymin),
+            self.xmax.min(other.xmax),
+            self.ymax.min(other.ymax),
+        );
+
+        if intersection.area() > 0.0 {
+            Some(intersection)
+        } else {
+            None
+        }
+    }
+
+    /// Intersection over union, from 0 for disjoint boxes to 1 for identical boxes
+    pub fn iou(&self, other: &BBox) -> f64 {
+        let intersection = self.intersection(other).map_or(0.0, |i| i.area());
+        let union = self.area() + other.area() - intersection;
+        if union > 0.0 {
+            intersection / union
+        } else {
+            0.0
+        }
+    }
+}
+
+#[test]
+fn test_conversions() {
+    let bbox = BBox::from_xyxy(10.0, 20.0, 50.0, 40.0);
+    assert_eq!(bbox.to_yxyx(), [20.0, 10.0, 40.0, 50.0]);
+    assert_eq!(bbox.to_xywh(), [10.0, 20.0, 40.0, 20.0]);
+    assert_eq!(bbox.to_cxcywh(), [30.0, 30.0, 40.0, 20.0]);
+    assert_eq!(BBox::from_yxyx(20.0, 10.0, 40.0, 50.0), bbox);
+    assert_eq!(BBox::from_xywh(10.0, 20.0, 40.0, 20.0), bbox);
+    assert_eq!(BBox::from_cxcywh(30.0, 30.0, 40.0, 20.0), bbox);
+    assert_eq!(bbox.area(), 800.0);
+    assert_eq!(bbox.aspect_ratio(), 2.0);
+
+    let normalized = bbox.normalize(100.0, 80.0);
+    assert_eq!(normalized, BBox::from_xyxy(0.1, 0.25, 0.5, 0.5));
+    assert_eq!(normalized.denormalize(100.0, 80.0), bbox);
+}
+
+#[test]
+fn test_operations() {
+    let a = BBox::from_xyxy(0.0, 0.0, 20.0, 10.0);
+    let b = BBox::from_xyxy(10.0, 0.0, 30.0, 10.0);
+    assert_eq!(
+        a.intersection(&b),
+        Some(BBox::from_xyxy(10.0, 0.0, 20.0, 10.0))
+    );
+    assert_eq!(a.iou(&b), 1.0 / 3.0);
+    assert_eq!(a.iou(&a), 1.0);
+    assert_eq!(a.intersection(&b.flip_horizontal(60.0)), None);
+    assert_eq!(a.iou(&b.flip_horizontal(60.0)), 0.0);
+
+    assert_eq!(
+        b.flip_horizontal(40.0),
+        BBox::from_xyxy(10.0, 0.0, 30.0, 10.0)
+    );
+    assert_eq!(
+        b.flip_vertical(50.0),
+        BBox::from_xyxy(10.0, 40.0, 30.0, 50.0)
+    );
+    assert_eq!(b.clip(25.0, 5.0), BBox::from_xyxy(10.0, 0.0, 25.0, 5.0));
+    assert_eq!(b.scale(0.5, 2.0), BBox::from_xyxy(5.0, 0.0, 15.0, 20.0));
+    assert_eq!(
+        BBox::from_xyxy(0.4, 1.6, 2.5, 3.0).round(),
+        BBox::from_xyxy(0.0, 2.0, 3.0, 3.0)
+    );
+}
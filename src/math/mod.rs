@@ -0,0 +1,523 @@
+//! Math helpers.
+use std::ops::Sub;
+
+use crc::crc32::{self, Hasher32};
+
+mod bbox;
+
+pub use bbox::BBox;
+
+/// Feature scaling helper - normalization (also called min-max scaling)
+pub fn normalize<T>(value: T, min: T, max: T) -> f64
+where
+    T: Copy + Sub<Output = T> + Into<f64>,
+{
+    (value - min).into() / (max - min).into()
+}
+
+/// IoU of two boxes of size (width, height) sharing the same center, as used to compare
+/// boxes with anchors. Ranges from 0 to 1, empty boxes have an IoU of 0.
+pub fn size_iou(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let intersection = a.0.min(b.0) * a.1.min(b.1);
+    let union = a.0 * a.1 + b.0 * b.1 - intersection;
+    if union > 0f64 {
+        intersection / union
+    } else {
+        0f64
+    }
+}
+
+/// Cluster box sizes (width, height) into `k` anchors with k-means, using `1 - IoU` as the
+/// distance so that large boxes don't dominate. The result is deterministic: anchors start
+/// at evenly spaced quantiles of the box areas. Anchors are sorted by area.
+pub fn kmeans_anchors(boxes: &[(f64, f64)], k: usize, max_iterations: usize) -> Vec<(f64, f64)> {
+    let k = k.min(boxes.len());
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut sorted = boxes.to_vec();
+    sorted.sort_by(|a, b| (a.0 * a.1).total_cmp(&(b.0 * b.1)));
+    let mut anchors: Vec<(f64, f64)> = (0..k)
+        .map(|i| sorted[(i * 2 + 1) * sorted.len() / (k * 2)])
+        .collect();
+
+    let mut assignments = vec![usize::max_value(); boxes.len()];
+    for _ in 0..max_iterations {
+        let mut changed = false;
+        boxes.iter().enumerate().for_each(|(index, size)| {
+            let closest = closest_anchor(*size, &anchors).0;
+            changed |= assignments[index] != closest;
+            assignments[index] = closest;
+        });
+        if !changed {
+            break;
+        }
+
+        // Each anchor moves to the mean size of its boxes, empty clusters don't move
+        anchors.iter_mut().enumerate().for_each(|(anchor, size)| {
+            let members: Vec<&(f64, f64)> = boxes
+                .iter()
+                .zip(&assignments)
+                .filter(|(_, assignment)| **assignment == anchor)
+                .map(|(size, _)| size)
+                .collect();
+            if !members.is_empty() {
+                let count = members.len() as f64;
+                *size = (
+                    members.iter().map(|s| s.0).sum::<f64>() / count,
+                    members.iter().map(|s| s.1).sum::<f64>() / count,
+                );
+            }
+        });
+    }
+
+    anchors.sort_by(|a, b| (a.0 * a.1).total_cmp(&(b.0 * b.1)));
+    anchors
+}
+
+/// Average, over all boxes, of the IoU with their closest anchor
+pub fn mean_iou(boxes: &[(f64, f64)], anchors: &[(f64, f64)]) -> f64 {
+    if boxes.is_empty() {
+        return 0f64;
+    }
+
+    let total: f64 = boxes.iter().map(|b| closest_anchor(*b, anchors).1).sum();
+    total / boxes.len() as f64
+}
+
+// Index of the anchor with the highest IoU, and that IoU
+fn closest_anchor(size: (f64, f64), anchors: &[(f64, f64)]) -> (usize, f64) {
+    anchors
+        .iter()
+        .map(|anchor| size_iou(size, *anchor))
+        .enumerate()
+        .fold(
+            (0, 0f64),
+            |best, (index, iou)| {
+                if iou > best.1 {
+                    (index, iou)
+                } else {
+                    best
+                }
+            },
+        )
+}
+
+/// Given some data and a ratio, for instance the bytes of an image and a ratio of 20%,
+/// this function computes whether or not or not the data should be retained.
+/// You can use it as a predicate to split a dataset between a training set and a testing set.
+/// This method is deteministic, applying it to a collection should result in datasets matching the
+/// specified ratio closely, but not perfectly.
+///
+/// ## Example
+/// ```rust
+/// use tftools::math::retain;
+///
+/// let input = "anything that can be converted to a slice of bytes";
+/// let ratio = 20; // For 20%
+/// let retained = retain(input, ratio);
+///
+/// // Nothing is retained with a ratio of 0%, everything with a ratio of 100%
+/// assert!(!retain(input, 0));
+/// assert!(retain(input, 100));
+/// ```
+pub fn retain<T>(input: T, ratio: u8) -> bool
+where
+    T: AsRef<[u8]>,
+{
+    let ratio = ratio.min(100) as f64 / 100f64;
+
+    // Compute a hash of the input, if the hash is below ratio% of the maximum
+    // hash value, it is retained.
+    let threshold = (u32::max_value() as f64 * ratio).round() as u32;
+    let bytes = input.as_ref();
+    let crc = crc32::checksum_ieee(&bytes);
+
+    crc < threshold
+}
+
+/// CRC32 of some data, salted with a seed. Different seeds give unrelated hashes.
+/// Without a seed, this is the hash used by `retain`.
+pub fn seeded_hash(input: &[u8], seed: Option<u64>) -> u32 {
+    match seed {
+        Some(seed) => {
+            let mut digest = crc32::Digest::new(crc32::IEEE);
+            digest.write(&seed.to_le_bytes());
+            digest.write(input);
+            digest.sum32()
+        }
+        None => crc32::checksum_ieee(input),
+    }
+}
+
+/// Generalization of `retain` to any number of sets. Given the CRC32 of some data and the ratio
+/// of each set, returns the index of the set the data should be placed in.
+/// Ratios are normalized, `[0.2, 0.8]` and `[1.0, 4.0]` are equivalent.
+/// Bucketing data in `[ratio, 1 - ratio]` gives index 0 exactly when `retain` would retain it.
+pub fn bucket(hash: u32, ratios: &[f64]) -> usize {
+    let total: f64 = ratios.iter().sum();
+    let mut cumulative = 0f64;
+
+    for (index, ratio) in ratios.iter().enumerate() {
+        cumulative += ratio / total;
+        let threshold = (u32::max_value() as f64 * cumulative).round() as u32;
+        if hash < threshold {
+            return index;
+        }
+    }
+
+    ratios.len().saturating_sub(1)
+}
+
+/// Split a number of elements between sets, according to the ratio of each set.
+/// Unlike `bucket`, the result is exact: counts are rounded with the largest remainder method
+/// and always add up to `count`.
+pub fn allocate(count: usize, ratios: &[f64]) -> Vec<usize> {
+    let total: f64 = ratios.iter().sum();
+    if ratios.is_empty() || total <= 0f64 {
+        return vec![0; ratios.len()];
+    }
+
+    let exact: Vec<f64> = ratios.iter().map(|r| count as f64 * r / total).collect();
+    let mut counts: Vec<usize> = exact.iter().map(|e| e.floor() as usize).collect();
+    let mut remainders: Vec<(usize, f64)> = exact
+        .iter()
+        .enumerate()
+        .map(|(index, e)| (index, e - e.floor()))
+        .collect();
+
+    // Largest remainders first, ties go to the first sets
+    remainders.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    let missing = count - counts.iter().sum::<usize>();
+    remainders
+        .iter()
+        .take(missing)
+        .for_each(|(index, _)| counts[*index] += 1);
+
+    counts
+}
+
+/// Value below which a share `q` of the values fall, for instance the median for `q = 0.5`.
+/// Values must be sorted, the nearest value is picked rather than interpolated.
+pub fn quantile(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0f64;
+    }
+
+    let index = (q.clamp(0f64, 1f64) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[index]
+}
+
+/// Keep the `count` items with the smallest hashes, in their original order.
+/// Hashes make the sample the same between runs, whatever the order of the items.
+pub fn sample<T, F>(items: Vec<T>, count: Option<usize>, hash: F) -> Vec<T>
+where
+    F: Fn(&T) -> u32,
+{
+    let count = match count {
+        Some(count) if count < items.len() => count,
+        _ => return items,
+    };
+
+    let mut hashes: Vec<(u32, usize)> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| (hash(item), index))
+        .collect();
+    hashes.sort();
+    let mut kept = vec![false; items.len()];
+    hashes
+        .iter()
+        .take(count)
+        .for_each(|(_, index)| kept[*index] = true);
+
+    items
+        .into_iter()
+        .zip(kept)
+        .filter(|(_, kept)| *kept)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Disjoint-set forest, used to merge elements that must end up together.
+/// Each set is represented by its smallest element, results don't depend on the order of unions.
+#[derive(Debug, Clone)]
+pub struct DisjointSets {
+    parents: Vec<usize>,
+}
+
+impl DisjointSets {
+    /// `len` singletons, from 0 to len - 1
+    pub fn new(len: usize) -> DisjointSets {
+        DisjointSets {
+            parents: (0..len).collect(),
+        }
+    }
+
+    /// Representative of the set containing `element`
+    pub fn find(&mut self, mut element: usize) -> usize {
+        while self.parents[element] != element {
+            self.parents[element] = self.parents[self.parents[element]];
+            element = self.parents[element];
+        }
+
+        element
+    }
+
+    /// Merge the sets containing `a` and `b`
+    pub fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parents[a.max(b)] = a.min(b);
+    }
+
+    /// All sets, ordered by their smallest element. Elements are sorted within each set.
+    pub fn sets(&mut self) -> Vec<Vec<usize>> {
+        let mut sets: Vec<Vec<usize>> = Vec::new();
+        let mut positions = vec![usize::max_value(); self.parents.len()];
+        for element in 0..self.parents.len() {
+            let root = self.find(element);
+            if positions[root] == usize::max_value() {
+                positions[root] = sets.len();
+                sets.push(Vec::new());
+            }
+            sets[positions[root]].push(element);
+        }
+
+        sets
+    }
+}
+
+/// Small seeded pseudo-random generator (SplitMix64), the same seed gives the same sequence on
+/// every platform.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform integer in `0..bound`, bound must not be 0
+    pub fn below(&mut self, bound: usize) -> usize {
+        // Multiply and keep the high bits, the bias is negligible for our bounds
+        ((u128::from(self.next_u64()) * bound as u128) >> 64) as usize
+    }
+
+    /// Shuffle a slice in place (Fisher-Yates)
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
+
+#[test]
+fn test_normalize() {
+    assert_eq!(normalize(50, 0, 100), 0.5);
+    assert_eq!(normalize(50.0, 0.0, 100.0), 0.5);
+    assert_eq!(normalize(10u32, 0u32, 100u32), 0.1);
+}
+
+#[test]
+fn test_size_iou() {
+    assert_eq!(size_iou((0.2, 0.4), (0.2, 0.4)), 1.0);
+    assert_eq!(size_iou((0.2, 0.4), (0.4, 0.2)), 1.0 / 3.0);
+    assert_eq!(size_iou((0.2, 0.2), (0.1, 0.1)), 0.25);
+    assert_eq!(size_iou((0.0, 0.0), (0.0, 0.0)), 0.0);
+}
+
+#[test]
+fn test_kmeans_anchors() {
+    // Two obvious clusters: small squares and wide boxes
+    let boxes = vec![
+        (0.1, 0.1),
+        (0.12, 0.1),
+        (0.1, 0.12),
+        (0.6, 0.3),
+        (0.62, 0.3),
+        (0.6, 0.32),
+    ];
+    let anchors = kmeans_anchors(&boxes, 2, 100);
+    assert_eq!(anchors.len(), 2);
+    assert!((anchors[0].0 - 0.1067).abs() < 1e-3 && (anchors[0].1 - 0.1067).abs() < 1e-3);
+    assert!((anchors[1].0 - 0.6067).abs() < 1e-3 && (anchors[1].1 - 0.3067).abs() < 1e-3);
+    assert!(mean_iou(&boxes, &anchors) > 0.85);
+    assert_eq!(kmeans_anchors(&boxes, 10, 100).len(), boxes.len());
+    assert!(kmeans_anchors(&[], 3, 100).is_empty());
+}
+
+#[test]
+fn test_split() {
+    let input = vec![
+        vec![1],
+        vec![2],
+        vec![3],
+        vec![4],
+        vec![5],
+        vec![6],
+        vec![7],
+        vec![8],
+        vec![9],
+        vec![10],
+    ];
+
+    let retained = input
+        .clone()
+        .iter()
+        .filter(|element| retain(element, 50))
+        .count();
+    assert_eq!(retained, 5);
+
+    let retained = input
+        .clone()
+        .iter()
+        .filter(|element| retain(element, 20))
+        .count();
+
+    // It SHOULD retain 2 elements.
+    // The CRC method provides a deterministic way to split the data
+    // and the ratio should be close enough to what we ask, but it's not perfect
+    assert_eq!(retained, 1);
+}
+
+#[test]
+fn test_retain_bounds() {
+    assert!(retain(vec![1], 100));
+    assert!(retain(vec![1], 150));
+    assert!(!retain(vec![1], 0));
+}
+
+#[test]
+fn test_seeded_hash() {
+    let input = include_bytes!("../../dataset/1.jpg");
+    assert_eq!(seeded_hash(input, None), crc32::checksum_ieee(input));
+    assert_eq!(seeded_hash(input, Some(1)), seeded_hash(input, Some(1)));
+    assert_ne!(seeded_hash(input, Some(1)), seeded_hash(input, Some(2)));
+    assert_ne!(seeded_hash(input, Some(1)), seeded_hash(input, None));
+}
+
+#[test]
+fn test_bucket() {
+    let input: Vec<Vec<u8>> = (1..=100).map(|i| vec![i]).collect();
+    let hash = |element: &Vec<u8>| crc32::checksum_ieee(element);
+
+    // Same result as retain for two sets
+    input.iter().for_each(|element| {
+        assert_eq!(bucket(hash(element), &[0.2, 0.8]) == 0, retain(element, 20));
+        assert_eq!(
+            bucket(hash(element), &[20.0, 80.0]) == 0,
+            retain(element, 20)
+        );
+    });
+
+    let counts = input.iter().fold([0; 3], |mut counts, element| {
+        counts[bucket(hash(element), &[0.7, 0.15, 0.15])] += 1;
+        counts
+    });
+    assert_eq!(counts.iter().sum::<usize>(), 100);
+    assert!(counts.iter().all(|count| *count > 0));
+}
+
+#[test]
+fn test_allocate() {
+    assert_eq!(allocate(10, &[0.2, 0.8]), vec![2, 8]);
+    assert_eq!(allocate(6, &[0.2, 0.8]), vec![1, 5]);
+    assert_eq!(allocate(3, &[0.5, 0.5]), vec![2, 1]);
+    assert_eq!(allocate(10, &[70.0, 15.0, 15.0]), vec![7, 2, 1]);
+    assert_eq!(allocate(0, &[0.5, 0.5]), vec![0, 0]);
+    assert_eq!(allocate(5, &[]), Vec::<usize>::new());
+}
+
+#[test]
+fn test_split_dataset() {
+    let input = vec![
+        include_bytes!("../../dataset/1.jpg").to_vec(),
+        include_bytes!("../../dataset/2.jpg").to_vec(),
+        include_bytes!("../../dataset/3.jpg").to_vec(),
+        include_bytes!("../../dataset/4.jpg").to_vec(),
+        include_bytes!("../../dataset/5.jpg").to_vec(),
+        include_bytes!("../../dataset/6.jpg").to_vec(),
+    ];
+
+    // Same comment, 20% is about 1.2 here it appears to be rounded to 2
+    let retained = input
+        .clone()
+        .iter()
+        .filter(|element| retain(element, 20))
+        .count();
+    assert_eq!(retained, 2);
+
+    let input = vec![
+        include_str!("../../dataset/1.xml"),
+        include_str!("../../dataset/2.xml"),
+        include_str!("../../dataset/3.xml"),
+        include_str!("../../dataset/4.xml"),
+        include_str!("../../dataset/5.xml"),
+        include_str!("../../dataset/6.xml"),
+    ];
+
+    // ... and here to one
+    let retained = input
+        .clone()
+        .iter()
+        .filter(|element| retain(element, 20))
+        .count();
+    assert_eq!(retained, 1);
+}
+
+#[test]
+fn test_quantile() {
+    let values = vec![1.0, 2.0, 3.0, 4.0, 10.0];
+    assert_eq!(quantile(&values, 0.0), 1.0);
+    assert_eq!(quantile(&values, 0.5), 3.0);
+    assert_eq!(quantile(&values, 0.75), 4.0);
+    assert_eq!(quantile(&values, 1.0), 10.0);
+    assert_eq!(quantile(&[], 0.5), 0.0);
+}
+
+#[test]
+fn test_sample() {
+    let items: Vec<u32> = (0..10).collect();
+    let hash = |i: &u32| 9 - *i;
+    assert_eq!(sample(items.clone(), Some(3), hash), vec![7, 8, 9]);
+    assert_eq!(sample(items.clone(), None, hash), items);
+    assert_eq!(sample(items.clone(), Some(20), hash), items);
+}
+
+#[test]
+fn test_disjoint_sets() {
+    let mut sets = DisjointSets::new(6);
+    sets.union(4, 1);
+    sets.union(5, 3);
+    sets.union(3, 1);
+
+    assert_eq!(sets.find(5), 1);
+    assert_eq!(sets.sets(), vec![vec![0], vec![1, 3, 4, 5], vec![2]]);
+}
+
+#[test]
+fn test_rng() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+    assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+    assert_ne!(Rng::new(8).next_u64(), first[0]);
+    assert!((0..100).all(|_| a.below(3) < 3));
+
+    let mut items: Vec<u32> = (0..10).collect();
+    a.shuffle(&mut items);
+    items.sort();
+    assert_eq!(items, (0..10).collect::<Vec<_>>());
+}
@@ -0,0 +1,165 @@
+//! This module draws bounding boxes and class names onto images, to review annotations.
+use image::{Rgb, RgbImage};
+use rusttype::{point, Font, Scale};
+
+use crate::math::{self, BBox};
+
+// Distinct colors, each class gets one based on the hash of its name
+const PALETTE: [[u8; 3]; 12] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [255, 225, 25],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+    [210, 245, 60],
+    [0, 128, 128],
+    [170, 110, 40],
+    [128, 0, 0],
+];
+
+/// Font used for class names
+pub fn default_font() -> Option<Font<'static>> {
+    Font::try_from_bytes(include_bytes!("../../assets/DejaVuSansMono.ttf"))
+}
+
+/// Color of a class, the same class always gets the same color
+pub fn class_color(name: &str) -> Rgb<u8> {
+    let hash = math::seeded_hash(name.as_bytes(), None) as usize;
+    Rgb(PALETTE[hash % PALETTE.len()])
+}
+
+/// Draw boxes, in pixels, and their class names. Names are written above each box, or inside
+/// when the box touches the top of the image.
+pub fn draw_boxes(image: &mut RgbImage, boxes: &[(BBox, String)], font: &Font) {
+    let (width, height) = image.dimensions();
+    let thickness = (width.min(height) / 300).max(2);
+    let scale = Scale::uniform((height as f32 / 40.0).max(12.0));
+
+    boxes.iter().for_each(|(bbox, name)| {
+        let color = class_color(name);
+        let bbox = bbox.clip(width as f64 - 1.0, height as f64 - 1.0).round();
+        let (xmin, ymin) = (bbox.xmin as u32, bbox.ymin as u32);
+        let (xmax, ymax) = (bbox.xmax as u32, bbox.ymax as u32);
+
+        // Outline
+        (0..thickness).for_each(|t| {
+            fill_rect(image, xmin, ymin + t, xmax, ymin + t, color);
+            fill_rect(
+                image,
+                xmin,
+                ymax.saturating_sub(t),
+                xmax,
+                ymax.saturating_sub(t),
+                color,
+            );
+            fill_rect(image, xmin + t, ymin, xmin + t, ymax, color);
+            fill_rect(
+                image,
+                xmax.saturating_sub(t),
+                ymin,
+                xmax.saturating_sub(t),
+                ymax,
+                color,
+            );
+        });
+
+        // Class name on a filled background
+        if name.is_empty() {
+            return;
+        }
+        let (text_width, text_height) = text_size(font, scale, name);
+        let top = if ymin >= text_height {
+            ymin - text_height
+        } else {
+            ymin
+        };
+        fill_rect(
+            image,
+            xmin,
+            top,
+            xmin + text_width,
+            top + text_height,
+            color,
+        );
+        draw_text(image, font, scale, xmin, top, name, Rgb([255, 255, 255]));
+    });
+}
+
+// Fill a rectangle, corners included, clipped to the image
+fn fill_rect(image: &mut RgbImage, xmin: u32, ymin: u32, xmax: u32, ymax: u32, color: Rgb<u8>) {
+    let (width, height) = image.dimensions();
+    for y in ymin..=ymax.min(height.saturating_sub(1)) {
+        for x in xmin..=xmax.min(width.saturating_sub(1)) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+// Size of a line of text, in pixels
+fn text_size(font: &Font, scale: Scale, text: &str) -> (u32, u32) {
+    let metrics = font.v_metrics(scale);
+    let width = font
+        .layout(text, scale, point(0.0, metrics.ascent))
+        .last()
+        .map_or(0.0, |g| {
+            g.position().x + g.unpositioned().h_metrics().advance_width
+        });
+
+    (
+        width.ceil() as u32,
+        (metrics.ascent - metrics.descent).ceil() as u32,
+    )
+}
+
+// Draw a line of text, (x, y) is its top left corner. Glyphs are blended with the background.
+fn draw_text(
+    image: &mut RgbImage,
+    font: &Font,
+    scale: Scale,
+    x: u32,
+    y: u32,
+    text: &str,
+    color: Rgb<u8>,
+) {
+    let (width, height) = image.dimensions();
+    let offset = point(x as f32, y as f32 + font.v_metrics(scale).ascent);
+
+    font.layout(text, scale, offset).for_each(|glyph| {
+        if let Some(bounds) = glyph.pixel_bounding_box() {
+            glyph.draw(|gx, gy, coverage| {
+                let px = bounds.min.x + gx as i32;
+                let py = bounds.min.y + gy as i32;
+                if px < 0 || py < 0 || px as u32 >= width || py as u32 >= height {
+                    return;
+                }
+
+                let pixel = image.get_pixel_mut(px as u32, py as u32);
+                for channel in 0..3 {
+                    let background = pixel[channel] as f32;
+                    let blended = background + (color[channel] as f32 - background) * coverage;
+                    pixel[channel] = blended.round() as u8;
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_draw_boxes() {
+    let font = default_font().unwrap();
+    let mut image = RgbImage::new(200, 100);
+    let bbox = BBox::from_xyxy(50.0, 40.0, 150.0, 90.0);
+    draw_boxes(&mut image, &[(bbox, "dog".to_owned())], &font);
+
+    let color = class_color("dog");
+    assert_eq!(*image.get_pixel(100, 40), color);
+    assert_eq!(*image.get_pixel(50, 60), color);
+    assert_eq!(*image.get_pixel(100, 65), Rgb([0, 0, 0]));
+    assert_eq!(class_color("dog"), color);
+
+    // The label sits above the box
+    assert_ne!(*image.get_pixel(52, 30), Rgb([0, 0, 0]));
+}
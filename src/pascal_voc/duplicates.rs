@@ -0,0 +1,258 @@
+//! This module finds duplicate images in a dataset. Exact duplicates have the same content,
+//! near-duplicates (resized, re-encoded or slightly edited copies) have close perceptual hashes.
+//! Relabeled copies of the same photo are a common source of leakage between sets.
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageError};
+use sha2::{Digest, Sha256};
+
+use super::parser::Annotation;
+
+/// What to do with duplicates when preparing a dataset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicateHandling {
+    /// Keep all images, each cluster is assigned to a single set
+    Group,
+    /// Keep the first image of each cluster, by annotation path, and drop the others
+    KeepOne,
+}
+
+/// Options for finding duplicates
+#[derive(Debug, Clone, Copy)]
+pub struct DuplicateOpts {
+    pub handling: DuplicateHandling,
+    /// Maximum number of different bits between the perceptual hashes of near-duplicates.
+    /// 0 only matches images that look identical once scaled down.
+    pub max_distance: u32,
+}
+
+/// Examples whose images are duplicates of each other
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cluster {
+    /// Indexes of the examples, ordered by annotation path
+    pub members: Vec<usize>,
+    /// All images have the same content
+    pub exact: bool,
+}
+
+/// Outcome of a search for duplicates
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    pub clusters: Vec<Cluster>,
+    /// Images that could not be read or decoded, they are not part of any cluster
+    pub unreadable: Vec<(PathBuf, ImageError)>,
+}
+
+// Content hash and perceptual hash of an image
+#[derive(Debug)]
+struct Fingerprint {
+    digest: Vec<u8>,
+    dhash: u64,
+}
+
+/// Find clusters of duplicate images among the images referenced by the annotations.
+/// Every image of a cluster is within `max_distance` of every other one, a chain of close images
+/// doesn't join images that are far apart.
+pub fn find_duplicates(input: &[Annotation], max_distance: u32) -> Duplicates {
+    let mut duplicates = Duplicates::default();
+    let fingerprints: Vec<Option<Fingerprint>> = input
+        .iter()
+        .map(|annotation| match fingerprint(annotation) {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(e) => {
+                duplicates
+                    .unreadable
+                    .push((annotation.system_path.clone(), e));
+                None
+            }
+        })
+        .collect();
+
+    let hashes: Vec<Option<u64>> = fingerprints
+        .iter()
+        .map(|f| f.as_ref().map(|f| f.dhash))
+        .collect();
+    duplicates.clusters = cluster_hashes(&hashes, max_distance)
+        .into_iter()
+        .map(|mut members| {
+            members.sort_by(|a, b| input[*a].annotation_path.cmp(&input[*b].annotation_path));
+            let digest = |i: &usize| fingerprints[*i].as_ref().map(|f| &f.digest);
+            let exact = members.iter().all(|i| digest(i) == digest(&members[0]));
+            Cluster { members, exact }
+        })
+        .collect();
+    duplicates.clusters.sort_by(|a, b| {
+        input[a.members[0]]
+            .annotation_path
+            .cmp(&input[b.members[0]].annotation_path)
+    });
+
+    duplicates
+}
+
+// Clusters of at least two hashes, every hash of a cluster is within `max_distance` of the others.
+// Closest pairs are merged first. Two clusters are merged when all their hashes are close enough,
+// so clusters never span more than `max_distance` bits.
+fn cluster_hashes(hashes: &[Option<u64>], max_distance: u32) -> Vec<Vec<usize>> {
+    let distance = |a: usize, b: usize| match (hashes[a], hashes[b]) {
+        (Some(a), Some(b)) => (a ^ b).count_ones(),
+        _ => u32::max_value(),
+    };
+    let mut pairs: Vec<(u32, usize, usize)> = candidate_pairs(hashes, max_distance)
+        .into_iter()
+        .map(|(a, b)| (distance(a, b), a, b))
+        .filter(|(d, _, _)| *d <= max_distance)
+        .collect();
+    pairs.sort_unstable();
+
+    // Cluster of each hash, and members of each cluster
+    let mut cluster_of: Vec<usize> = (0..hashes.len()).collect();
+    let mut clusters: Vec<Vec<usize>> = (0..hashes.len()).map(|i| vec![i]).collect();
+    for (_, a, b) in pairs {
+        let (a, b) = (cluster_of[a], cluster_of[b]);
+        let close = clusters[a]
+            .iter()
+            .all(|i| clusters[b].iter().all(|j| distance(*i, *j) <= max_distance));
+        if a == b || !close {
+            continue;
+        }
+        let (into, from) = if clusters[a].len() >= clusters[b].len() {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let moved = mem::take(&mut clusters[from]);
+        moved.iter().for_each(|i| cluster_of[*i] = into);
+        clusters[into].extend(moved);
+    }
+
+    clusters
+        .into_iter()
+        .filter(|members| members.len() > 1)
+        .collect()
+}
+
+// Pairs of hashes that may be within `max_distance`, instead of all pairs. Hashes are cut in
+// `max_distance + 1` parts: two hashes that differ by at most `max_distance` bits have at least
+// one identical part. Only hashes that share a part are paired.
+fn candidate_pairs(hashes: &[Option<u64>], max_distance: u32) -> BTreeSet<(usize, usize)> {
+    let parts = max_distance as usize + 1;
+    let mut buckets: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+    hashes
+        .iter()
+        .enumerate()
+        .for_each(|(index, hash)| match hash {
+            // Any two hashes are within 64 bits
+            Some(_) if parts > 64 => buckets.entry((0, 0)).or_default().push(index),
+            Some(hash) => {
+                (0..parts).for_each(|part| {
+                    let (start, end) = (part * 64 / parts, (part + 1) * 64 / parts);
+                    let bits = (hash >> start) & (u64::max_value() >> (64 - (end - start)));
+                    buckets.entry((part, bits)).or_default().push(index);
+                });
+            }
+            None => {}
+        });
+
+    let mut pairs = BTreeSet::new();
+    buckets.values().for_each(|bucket| {
+        for (i, a) in bucket.iter().enumerate() {
+            for b in &bucket[i + 1..] {
+                pairs.insert((*a, *b));
+            }
+        }
+    });
+
+    pairs
+}
+
+// Hash the content of the image referenced by an annotation, and its appearance
+fn fingerprint(annotation: &Annotation) -> Result<Fingerprint, ImageError> {
+    let bytes = fs::read(&annotation.system_path).map_err(ImageError::IoError)?;
+    let image = image::load_from_memory(&bytes)?;
+
+    Ok(Fingerprint {
+        digest: Sha256::digest(&bytes).to_vec(),
+        dhash: dhash(&image),
+    })
+}
+
+/// Difference hash: the image is scaled down to 9x8 grayscale pixels, each bit tells whether
+/// a pixel is darker than its right neighbour. Similar images have hashes with few different bits.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+#[test]
+fn test_find_duplicates() {
+    use std::env;
+
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    assert!(find_duplicates(&input, 0).clusters.is_empty());
+
+    // A relabeled copy of an image, and a smaller version of another one
+    let image = image::open("./dataset/2.jpg").unwrap();
+    let resized = env::temp_dir().join("pascal_voc_duplicates_2.png");
+    image.thumbnail(240, 240).save(&resized).unwrap();
+
+    let mut input = input;
+    let mut copy = input[0].clone();
+    copy.annotation_path = PathBuf::from("./dataset/1-copy.xml");
+    input.push(copy);
+    let mut copy = input[1].clone();
+    copy.annotation_path = PathBuf::from("./dataset/2-small.xml");
+    copy.system_path = resized;
+    input.push(copy);
+
+    let duplicates = find_duplicates(&input, 4);
+    assert_eq!(
+        duplicates.clusters,
+        vec![
+            Cluster {
+                members: vec![6, 0],
+                exact: true
+            },
+            Cluster {
+                members: vec![7, 1],
+                exact: false
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_cluster_hashes() {
+    // Bits far apart still pair up, through the parts of the hash they don't change
+    let spread = (1 << 63) | (1 << 31) | 1;
+    assert_eq!(
+        cluster_hashes(&[Some(0), Some(spread), None], 3),
+        vec![vec![0, 1]]
+    );
+    assert!(cluster_hashes(&[Some(0), Some(spread)], 2).is_empty());
+    assert_eq!(
+        cluster_hashes(&[Some(0), Some(u64::max_value())], 64),
+        vec![vec![0, 1]]
+    );
+
+    // A chain of close hashes doesn't join its ends, which are 8 bits apart
+    let chain = [Some(0), Some(0xf), Some(0xff)];
+    assert_eq!(cluster_hashes(&chain, 4), vec![vec![0, 1]]);
+    assert_eq!(cluster_hashes(&chain, 8), vec![vec![0, 1, 2]]);
+}
@@ -0,0 +1,171 @@
+//! This module implements anchor box suggestions for a PASCAL-VOC dataset.
+//! - Parse PASCAL-VOC files
+//! - Collect the normalized size of each box
+//! - Cluster sizes with k-means, using the IoU as a similarity
+use std::path::PathBuf;
+
+use super::prepare::get_xml_paths;
+use crate::math::{self, BBox};
+use crate::pascal_voc::parser::{Annotation, PascalVocError};
+
+// Upper bound on k-means iterations, clustering usually converges much earlier
+const MAX_ITERATIONS: usize = 300;
+
+/// Configuration options for suggesting anchor boxes
+#[derive(Debug)]
+pub struct AnchorsOpts {
+    /// Input directory, where your data set is. Will be searched recursively.
+    pub input: PathBuf,
+    /// Number of anchors.
+    pub clusters: usize,
+    /// Side of the square network input, used to express anchors in pixels.
+    pub input_size: u32,
+}
+
+// Takes a directory as a input, will recursively search for PASCAL-VOC files
+// and cluster the sizes of all boxes
+pub fn anchors(opts: AnchorsOpts) -> Report {
+    let mut report = Report {
+        input_size: opts.input_size,
+        ..Default::default()
+    };
+
+    let mut sizes = Vec::new();
+    get_xml_paths(&opts.input)
+        .iter()
+        .for_each(|path| match Annotation::from_file(path) {
+            Ok(annotation) => box_sizes(&annotation).into_iter().for_each(|size| {
+                // Empty boxes have no aspect ratio, they would make empty anchors
+                if size.0 > 0f64 && size.1 > 0f64 {
+                    sizes.push(size);
+                } else {
+                    report.empty_boxes += 1;
+                }
+            }),
+            Err(e) => report.invalid_annotations.push((path.to_owned(), e)),
+        });
+
+    report.boxes = sizes.len();
+    report.anchors = math::kmeans_anchors(&sizes, opts.clusters, MAX_ITERATIONS);
+    report.mean_iou = math::mean_iou(&sizes, &report.anchors);
+
+    report
+}
+
+// Normalized (width, height) of each box of an annotation
+fn box_sizes(annotation: &Annotation) -> Vec<(f64, f64)> {
+    let size = &annotation.size;
+    if size.width == 0 || size.height == 0 {
+        return Vec::new();
+    }
+
+    annotation
+        .objects
+        .iter()
+        .map(|o| BBox::from(&o.bndbox).normalize(size.width as f64, size.height as f64))
+        .map(|b| (b.width(), b.height()))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub invalid_annotations: Vec<(PathBuf, PascalVocError)>,
+    /// Number of boxes that were clustered
+    pub boxes: usize,
+    /// Number of boxes left out because their width or height is zero
+    pub empty_boxes: usize,
+    /// Normalized (width, height) of each anchor, sorted by area
+    pub anchors: Vec<(f64, f64)>,
+    /// Average IoU between each box and its closest anchor
+    pub mean_iou: f64,
+    pub input_size: u32,
+}
+
+impl Report {
+    pub fn print_report(&self) {
+        println!(
+            "Done, clustered {} boxes into {} anchors, mean IoU: {:.3}",
+            self.boxes,
+            self.anchors.len(),
+            self.mean_iou
+        );
+
+        if self.empty_boxes > 0 {
+            println!(
+                "Skipped {} box(es) with a zero width or height",
+                self.empty_boxes
+            );
+        }
+
+        let size = self.input_size as f64;
+        self.anchors.iter().for_each(|(width, height)| {
+            println!(
+                "   - {:.3} x {:.3}, {:.0} x {:.0} px at {}x{}, aspect ratio {:.2}",
+                width,
+                height,
+                width * size,
+                height * size,
+                self.input_size,
+                self.input_size,
+                width / height
+            );
+        });
+
+        if !self.anchors.is_empty() {
+            let scales: Vec<String> = self
+                .anchors
+                .iter()
+                .map(|(width, height)| format!("{:.3}", (width * height).sqrt()))
+                .collect();
+            let mut ratios: Vec<f64> = self.anchors.iter().map(|(w, h)| w / h).collect();
+            ratios.sort_by(|a, b| a.total_cmp(b));
+            let mut ratios: Vec<String> = ratios.iter().map(|r| format!("{:.2}", r)).collect();
+            ratios.dedup();
+            let pixels: Vec<String> = self
+                .anchors
+                .iter()
+                .map(|(width, height)| format!("{:.0},{:.0}", width * size, height * size))
+                .collect();
+
+            println!("SSD / Faster R-CNN:");
+            println!("   - scales: {}", scales.join(", "));
+            println!("   - aspect ratios (width / height): {}", ratios.join(", "));
+            println!("YOLO:");
+            println!("   - anchors = {}", pixels.join(",  "));
+        }
+
+        if !self.invalid_annotations.is_empty() {
+            println!(
+                "{} example(s) could not be processed:",
+                self.invalid_annotations.len(),
+            );
+            self.invalid_annotations.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+    }
+}
+
+#[test]
+fn test_empty_boxes() {
+    use std::fs;
+
+    let directory = std::env::temp_dir().join("tftools-test-anchors");
+    fs::create_dir_all(&directory).unwrap();
+    let xml = fs::read_to_string("./dataset/1.xml")
+        .unwrap()
+        .replace("<xmax>304</xmax>", "<xmax>208</xmax>")
+        .replace("<ymax>360</ymax>", "<ymax>240</ymax>");
+    fs::write(directory.join("1.xml"), xml).unwrap();
+
+    let report = anchors(AnchorsOpts {
+        input: directory.clone(),
+        clusters: 2,
+        input_size: 416,
+    });
+    assert_eq!(report.boxes, 1);
+    assert_eq!(report.empty_boxes, 1);
+    assert!(report.anchors.iter().all(|(w, h)| *w > 0f64 && *h > 0f64));
+    assert!(report.mean_iou.is_finite());
+    fs::remove_dir_all(&directory).unwrap();
+}
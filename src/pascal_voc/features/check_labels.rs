@@ -0,0 +1,224 @@
+//! This module checks that the classes of tfrecord files agree with a label map.
+//! - Read each record, one at a time
+//! - Check that each class ID is in the label map, and that the class text is its name
+//! - Report the classes of the label map that never occur
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::pascal_voc::label_map::LabelMap;
+use crate::pascal_voc::schema::Schema;
+use crate::pascal_voc::tfrecord::{decode_classes, RecordReader, TfRecordError};
+
+/// Configuration options for checking tfrecord files against a label map
+#[derive(Debug)]
+pub struct CheckLabelsOpts {
+    pub inputs: Vec<PathBuf>,
+    pub label_map: LabelMap,
+    /// Feature keys of the class texts and labels.
+    pub schema: Schema,
+}
+
+// Read every record of the tfrecord files and compare their classes with the label map
+pub fn check_labels(opts: CheckLabelsOpts) -> Result<Report, CheckLabelsError> {
+    let names: HashMap<i64, &str> = opts
+        .label_map
+        .items()
+        .into_iter()
+        .map(|(name, id)| (id, name))
+        .collect();
+    let mut report = Report::default();
+    let mut counts: HashMap<i64, usize> = HashMap::new();
+
+    for path in &opts.inputs {
+        let records =
+            RecordReader::open(path).map_err(|e| CheckLabelsError::Read(path.to_owned(), e))?;
+        for (index, record) in records.enumerate() {
+            let record = record.map_err(|e| CheckLabelsError::Read(path.to_owned(), e))?;
+            let (texts, labels) = decode_classes(&record, &opts.schema)
+                .map_err(|e| CheckLabelsError::Decode(path.to_owned(), index, e))?;
+            report.records += 1;
+
+            if !texts.is_empty() && !labels.is_empty() && texts.len() != labels.len() {
+                report.inconsistent_records += 1;
+            }
+            report.objects += texts.len().max(labels.len());
+
+            // Without labels, texts are looked up by name
+            if labels.is_empty() {
+                texts
+                    .iter()
+                    .for_each(|text| match opts.label_map.get(text) {
+                        Some(id) => *counts.entry(id).or_insert(0) += 1,
+                        None => *report.unknown_names.entry(text.clone()).or_insert(0) += 1,
+                    });
+                continue;
+            }
+
+            labels.iter().enumerate().for_each(|(i, &id)| {
+                let name = match names.get(&id) {
+                    Some(name) => name,
+                    None => {
+                        *report.unknown_ids.entry(id).or_insert(0) += 1;
+                        return;
+                    }
+                };
+                *counts.entry(id).or_insert(0) += 1;
+                if let Some(text) = texts.get(i) {
+                    if text != name {
+                        *report.mismatches.entry((id, text.clone())).or_insert(0) += 1;
+                    }
+                }
+            });
+        }
+    }
+
+    report.classes = opts
+        .label_map
+        .items()
+        .into_iter()
+        .map(|(name, id)| (id, (name.to_owned(), counts.get(&id).copied().unwrap_or(0))))
+        .collect();
+
+    Ok(report)
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub records: usize,
+    pub objects: usize,
+    /// Name and number of objects of each class of the label map, by ID
+    pub classes: BTreeMap<i64, (String, usize)>,
+    /// Number of objects whose ID is missing from the label map, by ID
+    pub unknown_ids: BTreeMap<i64, usize>,
+    /// Number of objects whose text isn't the name of their ID in the label map, by ID and text
+    pub mismatches: BTreeMap<(i64, String), usize>,
+    /// Number of objects whose text is missing from the label map, for records without labels
+    pub unknown_names: BTreeMap<String, usize>,
+    /// Number of records with a different number of texts and labels
+    pub inconsistent_records: usize,
+}
+
+impl Report {
+    /// Whether every class of the records is in the label map, with the right name.
+    /// Classes of the label map that never occur don't make the records inconsistent.
+    pub fn is_consistent(&self) -> bool {
+        self.unknown_ids.is_empty()
+            && self.mismatches.is_empty()
+            && self.unknown_names.is_empty()
+            && self.inconsistent_records == 0
+    }
+
+    /// Classes of the label map without any object
+    pub fn unused_classes(&self) -> Vec<(i64, &str)> {
+        self.classes
+            .iter()
+            .filter(|(_, (_, count))| *count == 0)
+            .map(|(&id, (name, _))| (id, name.as_str()))
+            .collect()
+    }
+
+    pub fn print_report(&self) {
+        println!(
+            "Checked {} record(s), {} object(s).",
+            self.records, self.objects
+        );
+
+        if !self.unknown_ids.is_empty() {
+            println!("IDs missing from the label map:");
+            self.unknown_ids.iter().for_each(|(id, count)| {
+                println!("   - {}: {} object(s)", id, count);
+            });
+        }
+
+        if !self.mismatches.is_empty() {
+            println!("Class texts that don't match the label map:");
+            self.mismatches.iter().for_each(|((id, text), count)| {
+                let expected = self.classes.get(id).map_or("", |(name, _)| name.as_str());
+                println!(
+                    "   - {}: {:?} instead of {:?}, {} object(s)",
+                    id, text, expected, count
+                );
+            });
+        }
+
+        if !self.unknown_names.is_empty() {
+            println!("Classes missing from the label map:");
+            self.unknown_names.iter().for_each(|(name, count)| {
+                println!("   - {}: {} object(s)", name, count);
+            });
+        }
+
+        if self.inconsistent_records > 0 {
+            println!(
+                "{} record(s) have a different number of class texts and labels.",
+                self.inconsistent_records
+            );
+        }
+
+        let unused = self.unused_classes();
+        if !unused.is_empty() {
+            println!("Classes of the label map that never occur:");
+            unused.iter().for_each(|(id, name)| {
+                println!("   - {}: {}", id, name);
+            });
+        }
+
+        if self.is_consistent() {
+            println!("Records and label map are consistent.");
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum CheckLabelsError {
+    #[error("Could not read {0:?}")]
+    Read(PathBuf, #[source] TfRecordError),
+
+    #[error("Could not decode record {1} of {0:?}")]
+    Decode(PathBuf, usize, #[source] TfRecordError),
+}
+
+#[test]
+fn test_check_labels() {
+    use crate::pascal_voc::parser::Annotation;
+    use crate::pascal_voc::tfrecord::RecordBuilder;
+    use std::path::Path;
+
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+    let directory = std::env::temp_dir();
+    let mut record = RecordBuilder::new(0, label_map.clone());
+    record
+        .add_example(Annotation::from_file(Path::new("./dataset/1.xml")).unwrap())
+        .unwrap();
+    let inputs = record
+        .write_tfrecord(&directory, "check_labels_test", 1)
+        .unwrap();
+
+    // IDs are swapped, and a class is missing
+    let mut other = LabelMap::new();
+    other.add("hotdog");
+    other.add("dog");
+    other.add("cat");
+    let report = check_labels(CheckLabelsOpts {
+        inputs: inputs.clone(),
+        label_map: other,
+        schema: Schema::od_api(),
+    })
+    .unwrap();
+    assert_eq!(report.records, 1);
+    assert_eq!(report.mismatches.len(), 2);
+    assert_eq!(report.unused_classes(), vec![(3, "cat")]);
+    assert!(!report.is_consistent());
+
+    let report = check_labels(CheckLabelsOpts {
+        inputs,
+        label_map,
+        schema: Schema::od_api(),
+    })
+    .unwrap();
+    assert!(report.is_consistent());
+}
@@ -0,0 +1,340 @@
+//! This module converts label maps between the formats expected by training frameworks.
+//! - Read a label map, its format is detected when it's not given
+//! - Write it in another format
+//!
+//! Lists (class names, Keras and YOLO) have no IDs, the class at position `i` gets the ID
+//! `first_id + i`. Label maps written as lists need consecutive IDs.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::pascal_voc::label_map::{LabelMap, LabelMapError};
+
+/// Formats of a label map
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LabelMapFormat {
+    /// Protobuf text format of the object detection API
+    Pbtxt,
+    /// One class name per line, `classes.txt`
+    Classes,
+    /// JSON object of names by ID, `{"1": "dog"}`
+    Json,
+    /// JSON list of class names, Keras `class_names`
+    Keras,
+    /// JSON list of COCO categories, `[{"id": 1, "name": "dog"}]`
+    Coco,
+    /// `names` of a YOLO `data.yaml` file
+    Yolo,
+}
+
+impl LabelMapFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            LabelMapFormat::Pbtxt => "pbtxt",
+            LabelMapFormat::Classes => "classes",
+            LabelMapFormat::Json => "json",
+            LabelMapFormat::Keras => "keras",
+            LabelMapFormat::Coco => "coco",
+            LabelMapFormat::Yolo => "yolo",
+        }
+    }
+}
+
+/// Configuration options for converting a label map
+#[derive(Debug)]
+pub struct ConvertOpts {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Format of the input, detected from its extension and content when missing.
+    pub from: Option<LabelMapFormat>,
+    pub to: LabelMapFormat,
+    /// ID of the first class of lists, 1 in the object detection API where 0 is the background.
+    pub first_id: i64,
+}
+
+// Read a label map and write it in another format
+pub fn convert(opts: ConvertOpts) -> Result<Report, ConvertError> {
+    let content = fs::read_to_string(&opts.input)?;
+    let from = match opts.from {
+        Some(format) => format,
+        None => detect_format(&opts.input, &content),
+    };
+
+    let label_map = read_label_map(&content, from, opts.first_id)?;
+    let mut output = write_label_map(&label_map, opts.to, opts.first_id)?;
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+    fs::write(&opts.output, output)?;
+
+    Ok(Report {
+        input: opts.input,
+        output: opts.output,
+        from,
+        to: opts.to,
+        classes: label_map.len(),
+    })
+}
+
+// Guess the format of a label map from its extension, then from its content
+fn detect_format(path: &Path, content: &str) -> LabelMapFormat {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "pbtxt" => LabelMapFormat::Pbtxt,
+        "yaml" | "yml" => LabelMapFormat::Yolo,
+        "json" => match serde_json::from_str(content) {
+            Ok(serde_json::Value::Array(values)) if values.iter().all(|v| v.is_string()) => {
+                LabelMapFormat::Keras
+            }
+            Ok(serde_json::Value::Object(object)) if !object.contains_key("categories") => {
+                LabelMapFormat::Json
+            }
+            _ => LabelMapFormat::Coco,
+        },
+        // label_map.txt is a pbtxt file, classes.txt is a list
+        _ if content.contains('{') => LabelMapFormat::Pbtxt,
+        _ => LabelMapFormat::Classes,
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct CocoCategory {
+    id: i64,
+    name: String,
+}
+
+// Categories can be read from a whole COCO dataset
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CocoCategories {
+    List(Vec<CocoCategory>),
+    Dataset { categories: Vec<CocoCategory> },
+}
+
+#[derive(Deserialize, Serialize)]
+struct YoloData {
+    #[serde(default, skip_deserializing)]
+    nc: usize,
+    names: YoloNames,
+}
+
+// Names are a list, or a map of names by index in recent versions
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum YoloNames {
+    List(Vec<String>),
+    Map(BTreeMap<i64, String>),
+}
+
+/// Parse a label map written in a given format
+pub fn read_label_map(
+    content: &str,
+    format: LabelMapFormat,
+    first_id: i64,
+) -> Result<LabelMap, ConvertError> {
+    let items: Vec<(i64, String)> = match format {
+        LabelMapFormat::Pbtxt => return Ok(LabelMap::from_pbtxt(content)?),
+        LabelMapFormat::Classes => from_list(
+            content
+                .lines()
+                .map(|line| line.trim().to_owned())
+                .filter(|line| !line.is_empty()),
+            first_id,
+        ),
+        LabelMapFormat::Json => {
+            let names: BTreeMap<String, String> = serde_json::from_str(content)?;
+            names
+                .into_iter()
+                .map(|(id, name)| {
+                    id.trim()
+                        .parse()
+                        .map(|id| (id, name))
+                        .map_err(|_| ConvertError::Id(id))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        LabelMapFormat::Keras => from_list(serde_json::from_str::<Vec<String>>(content)?, first_id),
+        LabelMapFormat::Coco => {
+            let categories = match serde_json::from_str(content)? {
+                CocoCategories::List(categories) => categories,
+                CocoCategories::Dataset { categories } => categories,
+            };
+            categories.into_iter().map(|c| (c.id, c.name)).collect()
+        }
+        LabelMapFormat::Yolo => match serde_yaml::from_str::<YoloData>(content)?.names {
+            YoloNames::List(names) => from_list(names, first_id),
+            YoloNames::Map(names) => names
+                .into_iter()
+                .map(|(index, name)| (index + first_id, name))
+                .collect(),
+        },
+    };
+
+    let mut label_map = LabelMap::new();
+    for (id, name) in items {
+        label_map.insert(&name, id)?;
+    }
+    Ok(label_map)
+}
+
+/// Write a label map in a given format
+pub fn write_label_map(
+    label_map: &LabelMap,
+    format: LabelMapFormat,
+    first_id: i64,
+) -> Result<String, ConvertError> {
+    let items = label_map.items();
+    let output = match format {
+        LabelMapFormat::Pbtxt => label_map.to_pbtxt(),
+        LabelMapFormat::Classes => {
+            let mut names = to_list(&items, first_id)?.join("\n");
+            names.push('\n');
+            names
+        }
+        LabelMapFormat::Json => {
+            let names: BTreeMap<i64, &str> = items.iter().map(|&(name, id)| (id, name)).collect();
+            serde_json::to_string_pretty(&names)?
+        }
+        LabelMapFormat::Keras => serde_json::to_string_pretty(&to_list(&items, first_id)?)?,
+        LabelMapFormat::Coco => {
+            let categories: Vec<CocoCategory> = items
+                .iter()
+                .map(|&(name, id)| CocoCategory {
+                    id,
+                    name: name.to_owned(),
+                })
+                .collect();
+            serde_json::to_string_pretty(&categories)?
+        }
+        LabelMapFormat::Yolo => {
+            let names: Vec<String> = to_list(&items, first_id)?
+                .into_iter()
+                .map(|n| n.to_owned())
+                .collect();
+            serde_yaml::to_string(&YoloData {
+                nc: names.len(),
+                names: YoloNames::List(names),
+            })?
+        }
+    };
+
+    Ok(output)
+}
+
+// Number the classes of a list
+fn from_list<I>(names: I, first_id: i64) -> Vec<(i64, String)>
+where
+    I: IntoIterator<Item = String>,
+{
+    names
+        .into_iter()
+        .enumerate()
+        .map(|(index, name)| (first_id + index as i64, name))
+        .collect()
+}
+
+// Names ordered by ID, the IDs have to follow each other from `first_id`
+fn to_list<'a>(items: &[(&'a str, i64)], first_id: i64) -> Result<Vec<&'a str>, ConvertError> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, &(name, id))| {
+            let expected = first_id + index as i64;
+            if id == expected {
+                Ok(name)
+            } else {
+                Err(ConvertError::NotConsecutive(expected, id))
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Report {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub from: LabelMapFormat,
+    pub to: LabelMapFormat,
+    /// Number of classes of the label map
+    pub classes: usize,
+}
+
+impl Report {
+    pub fn print_report(&self) {
+        println!(
+            "Converted {} classes from {:?} ({}) to {:?} ({}).",
+            self.classes,
+            self.input,
+            self.from.name(),
+            self.output,
+            self.to.name()
+        );
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConvertError {
+    #[error("An IO error occured while converting the label map")]
+    IoError(#[from] IoError),
+
+    #[error("Invalid label map")]
+    LabelMap(#[from] LabelMapError),
+
+    #[error("Invalid JSON label map")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Invalid YOLO data file")]
+    Yaml(#[from] serde_yaml::Error),
+
+    #[error("Invalid class ID {0:?}")]
+    Id(String),
+
+    #[error(
+        "Lists need consecutive IDs, expected ID {0} but got {1}. Pass the ID of the first class"
+    )]
+    NotConsecutive(i64, i64),
+}
+
+#[test]
+fn test_convert() {
+    let label_map = read_label_map("dog\nhotdog\n\n", LabelMapFormat::Classes, 1).unwrap();
+    assert_eq!(label_map.items(), vec![("dog", 1), ("hotdog", 2)]);
+
+    let formats = [
+        LabelMapFormat::Pbtxt,
+        LabelMapFormat::Classes,
+        LabelMapFormat::Json,
+        LabelMapFormat::Keras,
+        LabelMapFormat::Coco,
+        LabelMapFormat::Yolo,
+    ];
+    for &format in &formats {
+        let written = write_label_map(&label_map, format, 1).unwrap();
+        let read = read_label_map(&written, format, 1).unwrap();
+        assert_eq!(read.items(), label_map.items(), "{}", format.name());
+    }
+
+    let yolo = "train: images/train\nnames:\n  0: dog\n  1: hotdog\n";
+    let read = read_label_map(yolo, LabelMapFormat::Yolo, 1).unwrap();
+    assert_eq!(read.items(), label_map.items());
+
+    let coco =
+        r#"{"images": [], "categories": [{"id": 3, "name": "cat", "supercategory": "animal"}]}"#;
+    let read = read_label_map(coco, LabelMapFormat::Coco, 1).unwrap();
+    assert_eq!(read.items(), vec![("cat", 3)]);
+    assert!(matches!(
+        write_label_map(&read, LabelMapFormat::Keras, 1),
+        Err(ConvertError::NotConsecutive(1, 3))
+    ));
+    assert_eq!(
+        detect_format(Path::new("labels.json"), r#"["dog"]"#),
+        LabelMapFormat::Keras
+    );
+}
@@ -0,0 +1,107 @@
+//! This module implements the search for duplicate images in a PASCAL-VOC dataset.
+//! - Parse PASCAL-VOC files
+//! - Hash each image, by content and by appearance
+//! - Report clusters of exact and near-duplicates
+use std::path::PathBuf;
+
+use image::ImageError;
+
+use super::prepare::get_xml_paths;
+use crate::pascal_voc::duplicates::find_duplicates;
+use crate::pascal_voc::parser::{Annotation, PascalVocError};
+
+/// Configuration options for finding duplicate images
+#[derive(Debug)]
+pub struct DedupeOpts {
+    /// Input directory, where your data set is. Will be searched recursively.
+    pub input: PathBuf,
+    /// Maximum number of different bits between the perceptual hashes of near-duplicates.
+    pub max_distance: u32,
+}
+
+// Takes a directory as a input, will recursively search for PASCAL-VOC files
+// and report the clusters of duplicate images
+pub fn dedupe(opts: DedupeOpts) -> Report {
+    let mut report = Report::default();
+
+    let mut examples = Vec::new();
+    get_xml_paths(&opts.input)
+        .iter()
+        .for_each(|path| match Annotation::from_file(path) {
+            Ok(annotation) => examples.push(annotation),
+            Err(e) => report.invalid_annotations.push((path.to_owned(), e)),
+        });
+    report.valid_annotations = examples.len();
+
+    let duplicates = find_duplicates(&examples, opts.max_distance);
+    report.unreadable = duplicates.unreadable;
+    report.clusters = duplicates
+        .clusters
+        .into_iter()
+        .map(|cluster| ClusterReport {
+            exact: cluster.exact,
+            members: cluster
+                .members
+                .into_iter()
+                .map(|i| {
+                    let example = &examples[i];
+                    (example.annotation_path.clone(), example.system_path.clone())
+                })
+                .collect(),
+        })
+        .collect();
+
+    report
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub valid_annotations: usize,
+    pub invalid_annotations: Vec<(PathBuf, PascalVocError)>,
+    pub unreadable: Vec<(PathBuf, ImageError)>,
+    pub clusters: Vec<ClusterReport>,
+}
+
+impl Report {
+    pub fn print_report(&self) {
+        let duplicates: usize = self.clusters.iter().map(|c| c.members.len() - 1).sum();
+        println!(
+            "Done, found {} cluster(s) of duplicate images among {} examples, {} image(s) could be dropped.",
+            self.clusters.len(),
+            self.valid_annotations,
+            duplicates
+        );
+
+        self.clusters.iter().for_each(|cluster| {
+            let kind = if cluster.exact { "exact" } else { "near" };
+            println!("   - {} duplicates:", kind);
+            cluster.members.iter().for_each(|(annotation, image)| {
+                println!("      - {:?} ({:?})", annotation, image);
+            });
+        });
+
+        if !self.invalid_annotations.is_empty() {
+            println!(
+                "{} example(s) could not be processed:",
+                self.invalid_annotations.len(),
+            );
+            self.invalid_annotations.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+
+        if !self.unreadable.is_empty() {
+            println!("{} image(s) could not be read:", self.unreadable.len());
+            self.unreadable.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+    }
+}
+
+/// Examples of a cluster, as (annotation path, image path)
+#[derive(Debug, Default)]
+pub struct ClusterReport {
+    pub exact: bool,
+    pub members: Vec<(PathBuf, PathBuf)>,
+}
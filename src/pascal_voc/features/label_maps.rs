@@ -0,0 +1,325 @@
+//! This module implements the merge of label maps from several datasets into a single taxonomy.
+//! - Read each label map
+//! - Fold names that only differ by case or separators into the first spelling
+//! - Keep the ID of each class where possible, classes whose ID is taken get a new one
+//! - Keep the display name and metadata of the first definition of each class
+//! - Write the merged label map, and for each source a remapping table, as label rules
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::PathBuf;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::pascal_voc::label_map::{ClassInfo, LabelMap, LabelMapError};
+
+const LABEL_MAP_FILENAME: &str = "label_map.txt";
+
+/// Configuration options for merging label maps
+#[derive(Debug)]
+pub struct MergeOpts {
+    /// Label maps to merge, earlier ones keep their IDs first
+    pub inputs: Vec<PathBuf>,
+    /// Output directory, for the merged label map and the remapping tables
+    pub output: PathBuf,
+}
+
+// Takes several label maps and writes the merged one, with a remapping table for each source
+pub fn merge_label_maps(opts: MergeOpts) -> Result<Report, MergeError> {
+    let mut merged = LabelMap::new();
+    // Spelling kept for each normalized name
+    let mut spellings: HashMap<String, String> = HashMap::new();
+    let mut report = Report::default();
+
+    for path in &opts.inputs {
+        let source =
+            LabelMap::from_file(path).map_err(|e| MergeError::LabelMap(path.to_owned(), e))?;
+        let mut remapping = SourceReport {
+            path: path.to_owned(),
+            ..Default::default()
+        };
+
+        for (name, id) in source.items() {
+            let new_name = spellings
+                .entry(normalize(name))
+                .or_insert_with(|| name.to_owned())
+                .clone();
+            if new_name != name {
+                report.conflicts.push(Conflict::Name {
+                    source: path.to_owned(),
+                    name: name.to_owned(),
+                    merged_into: new_name.clone(),
+                });
+            }
+
+            let new_id = match (merged.get(&new_name), merged.label(id)) {
+                (Some(new_id), _) => new_id,
+                (None, None) => {
+                    merged.insert(&new_name, id)?;
+                    id
+                }
+                (None, Some(taken_by)) => {
+                    report.conflicts.push(Conflict::Id {
+                        source: path.to_owned(),
+                        id,
+                        name: name.to_owned(),
+                        taken_by: taken_by.to_owned(),
+                    });
+                    merged.add(&new_name)
+                }
+            };
+
+            remapping.entries.push(Remap {
+                name: name.to_owned(),
+                id,
+                new_name,
+                new_id,
+            });
+        }
+
+        // Metadata refers to IDs of the source, classes that are already defined keep their metadata
+        let new_ids: HashMap<i64, i64> =
+            remapping.entries.iter().map(|e| (e.id, e.new_id)).collect();
+        let remap_ids = |ids: &[i64]| {
+            ids.iter()
+                .filter_map(|id| new_ids.get(id))
+                .copied()
+                .collect()
+        };
+        remapping.entries.iter().for_each(|e| {
+            if let (Some(info), None) = (source.info(&e.name), merged.info(&e.new_name)) {
+                let info = ClassInfo {
+                    ancestor_ids: remap_ids(&info.ancestor_ids),
+                    descendant_ids: remap_ids(&info.descendant_ids),
+                    ..info.clone()
+                };
+                merged.set_info(&e.new_name, info);
+            }
+        });
+        report.sources.push(remapping);
+    }
+
+    // Write the merged label map, then the remapping table of each source
+    fs::create_dir_all(&opts.output)?;
+    let label_map_path = opts.output.join(LABEL_MAP_FILENAME);
+    merged.clone().write_to_file(&label_map_path)?;
+    report.label_map = label_map_path;
+    report.classes = merged.len();
+
+    let remap_dir = opts.output.join("remap");
+    fs::create_dir_all(&remap_dir)?;
+    for (index, source) in report.sources.iter_mut().enumerate() {
+        let stem = source
+            .path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let path = remap_dir.join(format!("{}-{}.toml", index + 1, stem));
+        fs::write(&path, source.to_label_rules())?;
+        source.rules = path;
+    }
+
+    Ok(report)
+}
+
+// Names that only differ by case or separators are the same class: Hot-Dog, hot_dog, hotdog
+fn normalize(name: &str) -> String {
+    name.to_lowercase()
+        .chars()
+        .filter(|c| !(c.is_whitespace() || *c == '-' || *c == '_'))
+        .collect()
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Path of the merged label map
+    pub label_map: PathBuf,
+    /// Number of classes in the merged label map
+    pub classes: usize,
+    /// Remapping of each source, in the order they were given
+    pub sources: Vec<SourceReport>,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// How the classes of a source label map are mapped to the merged one
+#[derive(Debug, Default)]
+pub struct SourceReport {
+    pub path: PathBuf,
+    /// Remapping table, as label rules for `prepare`
+    pub rules: PathBuf,
+    /// Every class of the source, ordered by ID
+    pub entries: Vec<Remap>,
+}
+
+#[derive(Debug)]
+pub struct Remap {
+    pub name: String,
+    pub id: i64,
+    pub new_name: String,
+    pub new_id: i64,
+}
+
+#[derive(Debug)]
+pub enum Conflict {
+    /// A name was folded into another spelling of the same class
+    Name {
+        source: PathBuf,
+        name: String,
+        merged_into: String,
+    },
+    /// The ID of a class was already taken by another class, the class got a new ID
+    Id {
+        source: PathBuf,
+        id: i64,
+        name: String,
+        taken_by: String,
+    },
+}
+
+// Rename rules of a remapping table
+#[derive(Serialize)]
+struct RenameTable<'a> {
+    rename: BTreeMap<&'a str, &'a str>,
+}
+
+impl SourceReport {
+    // Label rules renaming the classes of this source, with the whole table as comments
+    fn to_label_rules(&self) -> String {
+        let mut rules = String::new();
+        let _ = writeln!(
+            rules,
+            "# Remapping of {:?} into the merged label map",
+            self.path
+        );
+        let _ = writeln!(
+            rules,
+            "# Pass this file to `prepare --label-rules`, with the merged `--label-map`"
+        );
+        self.entries.iter().for_each(|e| {
+            let _ = writeln!(
+                rules,
+                "# {} ({}) -> {} ({})",
+                e.name, e.id, e.new_name, e.new_id
+            );
+        });
+
+        let table = RenameTable {
+            rename: self
+                .entries
+                .iter()
+                .filter(|e| e.name != e.new_name)
+                .map(|e| (e.name.as_str(), e.new_name.as_str()))
+                .collect(),
+        };
+        rules.push('\n');
+        rules.push_str(&toml::to_string(&table).unwrap_or_default());
+        rules
+    }
+}
+
+impl Report {
+    pub fn print_report(&self) {
+        println!(
+            "Merged label map written to {:?}, {} classes.",
+            self.label_map, self.classes
+        );
+
+        self.sources.iter().for_each(|source| {
+            let changed: Vec<&Remap> = source
+                .entries
+                .iter()
+                .filter(|e| e.name != e.new_name || e.id != e.new_id)
+                .collect();
+            println!(
+                "   - {:?}: {} classes, {} remapped, table written to {:?}",
+                source.path,
+                source.entries.len(),
+                changed.len(),
+                source.rules
+            );
+            changed.iter().for_each(|e| {
+                println!(
+                    "       {} ({}) -> {} ({})",
+                    e.name, e.id, e.new_name, e.new_id
+                );
+            });
+        });
+
+        if !self.conflicts.is_empty() {
+            println!("{} conflict(s) were resolved:", self.conflicts.len());
+            self.conflicts.iter().for_each(|conflict| match conflict {
+                Conflict::Name {
+                    source,
+                    name,
+                    merged_into,
+                } => println!(
+                    "   - In {:?} - {:?} was merged into {:?}",
+                    source, name, merged_into
+                ),
+                Conflict::Id {
+                    source,
+                    id,
+                    name,
+                    taken_by,
+                } => println!(
+                    "   - In {:?} - ID {} of {:?} is already used by {:?}",
+                    source, id, name, taken_by
+                ),
+            });
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum MergeError {
+    #[error("Could not read the label map {0:?}")]
+    LabelMap(PathBuf, #[source] LabelMapError),
+
+    #[error("Could not write the merged label map")]
+    Write(#[from] LabelMapError),
+
+    #[error("An IO error occured while writing the remapping tables")]
+    IoError(#[from] IoError),
+}
+
+#[test]
+fn test_merge_label_maps() {
+    let directory = std::env::temp_dir().join("tftools-test-merge-label-maps");
+    fs::create_dir_all(&directory).unwrap();
+    let first = directory.join("first.pbtxt");
+    let second = directory.join("second.pbtxt");
+    fs::write(
+        &first,
+        "item { name: 'dog' id: 1 } item { name: 'hotdog' id: 2 }",
+    )
+    .unwrap();
+    fs::write(
+        &second,
+        "item { name: 'Hot-Dog' id: 1 } item { name: 'cat' id: 2 display_name: 'Cat' ancestor_ids: 1 }",
+    )
+    .unwrap();
+
+    let report = merge_label_maps(MergeOpts {
+        inputs: vec![first, second],
+        output: directory.join("merged"),
+    })
+    .unwrap();
+
+    let merged = LabelMap::from_file(&report.label_map).unwrap();
+    assert_eq!(merged.items(), vec![("dog", 1), ("hotdog", 2), ("cat", 3)]);
+    let entries = &report.sources[1].entries;
+    assert_eq!(
+        (entries[0].new_name.as_str(), entries[0].new_id),
+        ("hotdog", 2)
+    );
+    assert_eq!(report.conflicts.len(), 2);
+    let cat = merged.info("cat").unwrap();
+    assert_eq!(cat.display_name.as_deref(), Some("Cat"));
+    assert_eq!(cat.ancestor_ids, vec![2]);
+
+    let rules = fs::read_to_string(&report.sources[1].rules).unwrap();
+    assert!(rules.contains("Hot-Dog = \"hotdog\""));
+    fs::remove_dir_all(&directory).unwrap();
+}
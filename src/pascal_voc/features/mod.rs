@@ -1,2 +1,10 @@
 //! All features available for working with PASCAL-VOC.
+pub mod anchors;
+pub mod check_labels;
+pub mod convert;
+pub mod dedupe;
+pub mod label_maps;
 pub mod prepare;
+pub mod preview;
+pub mod reshard;
+pub mod stats;
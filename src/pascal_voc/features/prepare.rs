@@ -1,20 +1,38 @@
 //! This module implements the logic necessary to prepare a PASCAL-VOC dataset.
 //! - Parse PASCAL-VOC files
+//! - Rename, merge and filter classes, optionally
+//! - Find duplicate images, optionally
 //! - Generate the label_map.txt file required by TensorFlow
-//! - Split the data into a training set and a test set
+//! - Split the data into named sets, for instance a training set and a test set
 //! - Generate tfrecord files for each set
+//! - Write an HTML report, optionally
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::io::Error as IoError;
 use std::path::{Path, PathBuf};
+use std::slice;
 
-use log::warn;
+use indicatif::{HumanBytes, ProgressBar};
+use log::{info, warn};
+use serde::Serialize;
 use thiserror::Error;
 use walkdir::WalkDir;
 
 use crate::math;
-use crate::pascal_voc::label_map::{LabelMap, LabelMapError};
+use crate::pascal_voc::duplicates::{find_duplicates, DuplicateHandling, DuplicateOpts};
+use crate::pascal_voc::html;
+use crate::pascal_voc::label_map::{ClassDefinitions, LabelMap, LabelMapError};
+use crate::pascal_voc::label_rules::{LabelMapping, LabelRules};
+use crate::pascal_voc::manifest::{example_id, manifest_dir, Manifest, ManifestError};
 use crate::pascal_voc::parser::{Annotation, PascalVocError};
-use crate::pascal_voc::tfrecord::{RecordBuilder, TfRecordError};
+use crate::pascal_voc::schema::Schema;
+use crate::pascal_voc::split::{group_key, images_per_class, split_dataset, SplitOpts, SplitSpec};
+use crate::pascal_voc::tfrecord::{
+    write_shards, ImageEncoding, RecordBuilder, TfRecordError, Transcoding,
+};
+use crate::progress::Progress;
 
 /// Configuration options for preparing TensorFlow input files
 /// from PASCAL-VOC annotated images
@@ -24,105 +42,676 @@ pub struct PrepareOpts {
     pub input: PathBuf,
     /// Output directory, where the TensorFlow configuration files will be written.
     pub output: PathBuf,
-    /// Percentage of data that should be placed in the test set.
-    pub test_set_ratio: u8,
+    /// Sets the data is split into, each one is written to its own tfrecord file.
+    pub splits: Vec<SplitSpec>,
+    /// How examples are assigned to each set.
+    pub split: SplitOpts,
+    /// Generate folds for k-fold cross-validation instead of the sets described by `splits`.
+    pub folds: Option<usize>,
+    /// Directory of a split manifest from a previous run. Examples listed in the manifest keep
+    /// their set, new examples are split as usual.
+    pub from_manifest: Option<PathBuf>,
+    /// Rename, merge and filter classes before the label map is generated.
+    pub label_rules: Option<LabelRules>,
+    /// Use this label map instead of generating one, for consistent IDs between datasets.
+    pub label_map: Option<LabelMap>,
+    /// Display names and metadata of the classes, written to the label map.
+    pub classes: Option<ClassDefinitions>,
+    /// Look for duplicate images, and either keep them in the same set or keep one per cluster.
+    pub duplicates: Option<DuplicateOpts>,
+    /// Number of tfrecord files written for each set, of similar sizes.
+    pub shards: usize,
+    /// Format used for images that are not PNG or JPEG.
+    pub image_encoding: ImageEncoding,
+    /// Leave objects flagged as difficult out of the training sets.
+    /// They are still written to other sets, where evaluators can ignore them.
+    pub exclude_difficult: bool,
+    /// Names of the training sets, for `exclude_difficult`. The training sets of folds are
+    /// always training sets.
+    pub training_sets: Vec<String>,
+    /// Reference the source annotation file (path and SHA-256) in each example.
+    pub provenance: bool,
+    /// Feature keys and formats of the output examples.
+    pub schema: Schema,
+    /// Write a self-contained `report.html` next to the tfrecord files.
+    pub html_report: bool,
+    /// How the report is printed.
+    pub report_format: ReportFormat,
+    /// Conditions that make the run fail, see `Report::failures`.
+    pub fail_on: Vec<FailOn>,
+    /// Show progress bars on stderr, they are hidden anyway when stderr is not a terminal.
+    pub progress: bool,
 }
 
+/// How the report is printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+/// Data quality issues that make the run fail. Each one has its own exit code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailOn {
+    /// Some annotations could not be parsed
+    Invalid,
+    /// A set has no examples, its tfrecord file was not written
+    EmptySplit,
+    /// Some examples were left out of the tfrecord files
+    Dropped,
+}
+
+impl FailOn {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            FailOn::Invalid => 2,
+            FailOn::EmptySplit => 3,
+            FailOn::Dropped => 4,
+        }
+    }
+}
+
+impl fmt::Display for FailOn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FailOn::Invalid => write!(f, "some annotations are invalid"),
+            FailOn::EmptySplit => write!(f, "a set is empty"),
+            FailOn::Dropped => write!(f, "some examples were left out of the tfrecord files"),
+        }
+    }
+}
+
+const LABEL_MAP_FILENAME: &str = "label_map.txt";
+
+// Number of examples shown as thumbnails for each set in the HTML report
+const REPORT_SAMPLES: usize = 12;
+
 // Takes a directory as a input, will recursively search for PASCAL-VOC files
 // and generate tfrecord files in the output directory
-pub fn prepare(opts: PrepareOpts) -> Result<Report, PrepareError> {
+pub fn prepare(mut opts: PrepareOpts) -> Result<Report, PrepareError> {
+    // Excluding difficult objects from no set at all is surely a mistake in the set names
+    if opts.exclude_difficult
+        && opts.folds.is_none()
+        && !opts
+            .splits
+            .iter()
+            .any(|s| opts.training_sets.contains(&s.name))
+    {
+        return Err(PrepareError::NoTrainingSet(opts.training_sets.clone()));
+    }
+
     // Make sure output directory exist, create it otherwise
     fs::create_dir_all(&opts.output)?;
 
     // Report information while processing the dataset
     let mut report = Report::default();
+    let progress = Progress::new(opts.progress);
 
     // Collect all annotations
+    let scanned = progress.spinner("Scanning files");
+    let paths = scan_xml_paths(&opts.input, &scanned);
+    scanned.finish_and_clear();
+    info!("Found {} PASCAL-VOC files in {:?}", paths.len(), opts.input);
+
+    let parsed = progress.bar(paths.len(), "Parsing annotations");
     let mut input_examples = Vec::new();
-    get_xml_paths(&opts.input)
-        .iter()
-        .for_each(|path| match Annotation::from_file(path) {
+    paths.iter().for_each(|path| {
+        match Annotation::from_file(path) {
             Ok(annotation) => {
                 input_examples.push(annotation);
                 report.valid_annotations += 1;
             }
             Err(e) => report.invalid_annotations.push((path.to_owned(), e)),
-        });
+        }
+        parsed.inc(1);
+    });
+    parsed.finish_and_clear();
+
+    // Rename, merge and filter classes
+    if let Some(rules) = &opts.label_rules {
+        report.labels = rules.apply(&mut input_examples);
+        info!(
+            "Label rules renamed {} class(es) and left out {} object(s)",
+            report.labels.renamed.len(),
+            report.labels.dropped.values().sum::<usize>()
+        );
+    }
+
+    // Group or drop duplicate images
+    if let Some(duplicates) = opts.duplicates {
+        input_examples = handle_duplicates(&mut opts, input_examples, duplicates, &mut report);
+    }
 
     // Build and write label map
     let label_map = gen_label_map(&opts, &input_examples)?;
-    report.labels = true;
+    let label_map_path = opts.output.join(LABEL_MAP_FILENAME);
+    info!("Label map written to {:?}", label_map_path);
+    report.label_map = Some(label_map_path);
 
-    // Split dataset in two and generate tfrecord file for each set
-    let (test, train) = split_dataset(input_examples, opts.test_set_ratio);
-    if gen_tfrecord(&opts, test, &label_map, "test.tfrecord")?.is_some() {
-        report.test = true;
+    // Split dataset and generate a tfrecord file for each set
+    let mut context = WriteContext {
+        written: WriteProgress::new(progress.bar(input_examples.len(), "Writing examples")),
+        report: &mut report,
+    };
+    match opts.folds {
+        Some(folds) => gen_folds(&opts, input_examples, &label_map, folds, &mut context)?,
+        None => gen_splits(&opts, input_examples, &label_map, &mut context)?,
     }
+    context.written.bar.finish_and_clear();
 
-    if gen_tfrecord(&opts, train, &label_map, "train.tfrecord")?.is_some() {
-        report.train = true;
+    // Write the HTML report
+    if opts.html_report {
+        let path = opts.output.join("report.html");
+        html::write_report(&path, &report)?;
+        report.html_report = Some(path);
     }
 
     Ok(report)
 }
 
-// Split the dataset in two, a training set and a testing set.
-// Tuple structure is (test, train)
-fn split_dataset(input: Vec<Annotation>, ratio: u8) -> (Vec<Annotation>, Vec<Annotation>) {
-    input.into_iter().partition(|annotation| {
-        fs::read(&annotation.system_path)
-            .map(|bytes| math::retain(bytes, ratio))
-            .unwrap_or(false)
+// Split the dataset according to the split specifications, one tfrecord file per set
+fn gen_splits(
+    opts: &PrepareOpts,
+    examples: Vec<Annotation>,
+    label_map: &LabelMap,
+    context: &mut WriteContext,
+) -> Result<(), PrepareError> {
+    let sets = split_examples(opts, examples, &opts.splits, context.report)?;
+    for (spec, mut examples) in opts.splits.iter().zip(sets) {
+        let mut split = SplitReport {
+            name: spec.name.clone(),
+            class_counts: count_classes(&examples),
+            ..Default::default()
+        };
+        if opts.html_report {
+            split.samples = sample_paths(&examples);
+        }
+
+        if opts.exclude_difficult && opts.training_sets.contains(&spec.name) {
+            context.report.excluded_difficult += exclude_difficult(&mut examples);
+        }
+
+        gen_tfrecord(opts, examples, label_map, &spec.name, &mut split, context)?;
+        context.report.splits.push(split);
+    }
+
+    Ok(())
+}
+
+// Generate one directory per fold for k-fold cross-validation. In each directory, the test set
+// is made of the fold and the training set of all other folds.
+// Examples are encoded once and reused by every fold.
+fn gen_folds(
+    opts: &PrepareOpts,
+    examples: Vec<Annotation>,
+    label_map: &LabelMap,
+    folds: usize,
+    context: &mut WriteContext,
+) -> Result<(), PrepareError> {
+    let specs: Vec<SplitSpec> = (0..folds)
+        .map(|i| SplitSpec::new(&format!("fold-{}", i), 1f64))
+        .collect();
+    let sets = split_examples(opts, examples, &specs, context.report)?;
+    let class_counts: Vec<_> = sets.iter().map(|set| count_classes(set)).collect();
+    let samples: Vec<_> = sets
+        .iter()
+        .map(|set| {
+            if opts.html_report {
+                sample_paths(set)
+            } else {
+                Vec::new()
+            }
+        })
+        .collect();
+
+    let record = record_builder(opts, label_map);
+    let encoded: Vec<FoldRecords> = sets
+        .into_iter()
+        .map(|set| encode_fold(opts, &record, set, context))
+        .collect();
+
+    for (i, spec) in specs.iter().enumerate() {
+        let directory = opts.output.join(&spec.name);
+        fs::create_dir_all(&directory)?;
+
+        let mut test = SplitReport {
+            name: format!("{}/test", spec.name),
+            class_counts: class_counts[i].clone(),
+            samples: samples[i].clone(),
+            ..Default::default()
+        };
+        let records = encoded[i].test.iter().map(Vec::as_slice);
+        write_set(opts, &directory, "test", records, &mut test)?;
+        context.report.splits.push(test);
+
+        let mut train = SplitReport {
+            name: format!("{}/train", spec.name),
+            ..Default::default()
+        };
+        class_counts
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .flat_map(|(_, counts)| counts.iter())
+            .for_each(|(name, count)| {
+                *train.class_counts.entry(name.clone()).or_insert(0) += count;
+            });
+        let records = encoded
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .flat_map(|(_, fold)| fold.train());
+        write_set(opts, &directory, "train", records, &mut train)?;
+        context.report.splits.push(train);
+    }
+
+    Ok(())
+}
+
+// Serialized examples of a fold
+#[derive(Debug, Default)]
+struct FoldRecords {
+    // Examples, as written to test sets
+    test: Vec<Vec<u8>>,
+    // Alternative version of each example for training sets, when it differs
+    // from the test version (difficult objects were excluded)
+    train: Vec<Option<Vec<u8>>>,
+}
+
+impl FoldRecords {
+    // Examples, as written to training sets
+    fn train(&self) -> impl Iterator<Item = &[u8]> {
+        self.test
+            .iter()
+            .zip(&self.train)
+            .map(|(test, train)| train.as_deref().unwrap_or(test.as_slice()))
+    }
+}
+
+// Encode all examples of a fold
+fn encode_fold(
+    opts: &PrepareOpts,
+    record: &RecordBuilder,
+    examples: Vec<Annotation>,
+    context: &mut WriteContext,
+) -> FoldRecords {
+    let mut fold = FoldRecords::default();
+    examples.into_iter().for_each(|example| {
+        let path = example.system_path.clone();
+        let train = if opts.exclude_difficult && example.objects.iter().any(|o| o.difficult) {
+            let mut train = example.clone();
+            let excluded = exclude_difficult(slice::from_mut(&mut train));
+            Some((train, excluded))
+        } else {
+            None
+        };
+
+        let encoded = record.encode_example(example);
+        context
+            .written
+            .add(encoded.as_ref().map_or(0, |e| e.bytes.len()));
+        // The example is dropped from every set when either version can't be encoded, objects
+        // only count as excluded once the training version is encoded
+        let encoded = encoded.and_then(|encoded| match train {
+            Some((train, excluded)) => record.encode_example(train).map(|train| {
+                context.report.excluded_difficult += excluded;
+                (encoded, Some(train.bytes))
+            }),
+            None => Ok((encoded, None)),
+        });
+        match encoded {
+            Ok((encoded, train)) => {
+                if let Some(transcoding) = encoded.transcoding {
+                    context.report.transcoded.push((path, transcoding));
+                }
+                fold.test.push(encoded.bytes);
+                fold.train.push(train);
+            }
+            Err(e) => context.report.dropped_examples.push((path, e)),
+        }
+    });
+
+    fold
+}
+
+// Write serialized examples to the tfrecord files of a set, nothing is written for empty sets
+fn write_set<'a, I>(
+    opts: &PrepareOpts,
+    directory: &Path,
+    name: &str,
+    records: I,
+    split: &mut SplitReport,
+) -> Result<(), PrepareError>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let records: Vec<&[u8]> = records.collect();
+    if records.is_empty() {
+        warn!(
+            "{} dataset is empty, tfrecord won't be generated",
+            split.name
+        );
+        return Ok(());
+    }
+
+    split.examples = records.len();
+    split.files = write_shards(directory, name, records, opts.shards)?;
+    info!("{} example(s) written to {:?}", split.examples, split.files);
+
+    Ok(())
+}
+
+// Assign examples to sets. Examples listed in the manifest passed with `from_manifest` keep
+// their set, others are split according to the split options.
+// The resulting assignment is written to the manifest in the output directory.
+fn split_examples(
+    opts: &PrepareOpts,
+    examples: Vec<Annotation>,
+    specs: &[SplitSpec],
+    report: &mut Report,
+) -> Result<Vec<Vec<Annotation>>, PrepareError> {
+    let names: Vec<&str> = specs.iter().map(|s| s.name.as_str()).collect();
+    let (forced, remaining) = match &opts.from_manifest {
+        Some(directory) => {
+            let manifest = Manifest::from_dir(directory)?;
+            let assigned =
+                assign_from_manifest(&opts.input, examples, &names, &manifest, &opts.split)?;
+            report.from_manifest = assigned.count;
+            (assigned.listed, assigned.remaining)
+        }
+        None => (specs.iter().map(|_| Vec::new()).collect(), examples),
+    };
+
+    let mut sets = split_dataset(remaining, specs, &opts.split);
+    sets.iter_mut()
+        .zip(forced)
+        .for_each(|(set, forced)| set.extend(forced));
+
+    let directory = manifest_dir(&opts.output);
+    Manifest::new(&opts.input, &names, &sets).write_to_dir(&directory)?;
+    report.manifest = Some(directory);
+
+    Ok(sets)
+}
+
+// Examples assigned by a manifest
+struct ManifestAssignment {
+    // Examples of each set: the ones listed in the manifest, and their duplicates
+    listed: Vec<Vec<Annotation>>,
+    // Examples left to split
+    remaining: Vec<Annotation>,
+    // Number of examples listed in the manifest
+    count: usize,
+}
+
+// Listed examples keep their set. Duplicates and groups must share a set: unlisted examples
+// follow the listed members of their unit, and units listed in different sets are an error.
+fn assign_from_manifest(
+    root: &Path,
+    examples: Vec<Annotation>,
+    names: &[&str],
+    manifest: &Manifest,
+    split: &SplitOpts,
+) -> Result<ManifestAssignment, ManifestError> {
+    if let Some(unknown) = manifest.set_names().find(|name| !names.contains(name)) {
+        return Err(ManifestError::UnknownSet(unknown.to_owned()));
+    }
+
+    let assignments = manifest.assignments()?;
+    let ids: Vec<String> = examples.iter().map(|e| example_id(root, e)).collect();
+    let sets: Vec<Option<usize>> = ids
+        .iter()
+        .map(|id| {
+            assignments
+                .get(id.as_str())
+                .map(|name| names.iter().position(|n| n == name).unwrap_or(0))
+        })
+        .collect();
+
+    // Examples that must share a set: duplicates, and groups of `group_by`
+    let mut units = math::DisjointSets::new(examples.len());
+    let mut first_in_cluster: HashMap<usize, usize> = HashMap::new();
+    let mut first_in_group: HashMap<String, usize> = HashMap::new();
+    examples.iter().enumerate().for_each(|(index, example)| {
+        if let Some(cluster) = split.clusters.get(&example.annotation_path) {
+            let first = *first_in_cluster.entry(*cluster).or_insert(index);
+            units.union(first, index);
+        }
+        if let Some(group_by) = &split.group_by {
+            let key = group_key(example, group_by);
+            let first = *first_in_group.entry(key).or_insert(index);
+            units.union(first, index);
+        }
+    });
+
+    // A listed member of each unit, all listed members must be in the same set
+    let mut unit_members: HashMap<usize, usize> = HashMap::new();
+    for (index, set) in sets.iter().enumerate() {
+        let set = match set {
+            Some(set) => *set,
+            None => continue,
+        };
+        let member = *unit_members.entry(units.find(index)).or_insert(index);
+        if sets[member] != Some(set) {
+            return Err(ManifestError::Separated(
+                ids[member].clone(),
+                ids[index].clone(),
+                names[sets[member].unwrap_or(0)].to_owned(),
+                names[set].to_owned(),
+            ));
+        }
+    }
+
+    let mut assigned = ManifestAssignment {
+        listed: names.iter().map(|_| Vec::new()).collect(),
+        remaining: Vec::new(),
+        count: sets.iter().filter(|set| set.is_some()).count(),
+    };
+    examples
+        .into_iter()
+        .enumerate()
+        .for_each(|(index, example)| {
+            let set = unit_members
+                .get(&units.find(index))
+                .and_then(|member| sets[*member]);
+            match set {
+                Some(set) => assigned.listed[set].push(example),
+                None => assigned.remaining.push(example),
+            }
+        });
+
+    Ok(assigned)
+}
+
+// Find clusters of duplicate images. Either the first example of each cluster is kept, or
+// clusters are passed to the split so that each one is assigned to a single set.
+fn handle_duplicates(
+    opts: &mut PrepareOpts,
+    examples: Vec<Annotation>,
+    duplicates: DuplicateOpts,
+    report: &mut Report,
+) -> Vec<Annotation> {
+    let found = find_duplicates(&examples, duplicates.max_distance);
+    found.unreadable.iter().for_each(|(path, e)| {
+        warn!("{:?} could not be checked for duplicates: {}", path, e);
+    });
+
+    let mut dropped = vec![false; examples.len()];
+    found.clusters.iter().enumerate().for_each(|(cluster, c)| {
+        report.duplicates.push(
+            c.members
+                .iter()
+                .map(|i| examples[*i].annotation_path.clone())
+                .collect(),
+        );
+        c.members
+            .iter()
+            .enumerate()
+            .for_each(|(rank, i)| match duplicates.handling {
+                DuplicateHandling::Group => {
+                    let path = examples[*i].annotation_path.clone();
+                    opts.split.clusters.insert(path, cluster);
+                }
+                DuplicateHandling::KeepOne => dropped[*i] = rank > 0,
+            });
+    });
+
+    report.dropped_duplicates = dropped.iter().filter(|d| **d).count();
+    examples
+        .into_iter()
+        .zip(dropped)
+        .filter(|(_, dropped)| !dropped)
+        .map(|(example, _)| example)
+        .collect()
+}
+
+// Count images containing each class
+fn count_classes(examples: &[Annotation]) -> BTreeMap<String, usize> {
+    images_per_class(examples)
+        .into_iter()
+        .map(|(name, count)| (name.to_owned(), count))
+        .collect()
+}
+
+// Annotation paths of the examples shown in the HTML report
+fn sample_paths(examples: &[Annotation]) -> Vec<PathBuf> {
+    let paths = examples.iter().map(|e| e.annotation_path.clone()).collect();
+    math::sample(paths, Some(REPORT_SAMPLES), |path: &PathBuf| {
+        math::seeded_hash(path.to_string_lossy().as_bytes(), None)
     })
 }
 
-// Generate the label map and write it to a file
+// Remove objects flagged as difficult, returns how many were removed
+fn exclude_difficult(examples: &mut [Annotation]) -> usize {
+    examples.iter_mut().fold(0, |removed, example| {
+        let count = example.objects.len();
+        example.objects.retain(|o| !o.difficult);
+        removed + count - example.objects.len()
+    })
+}
+
+// Generate the label map, or check the one of the options, and write it to a file
 fn gen_label_map(opts: &PrepareOpts, examples: &[Annotation]) -> Result<LabelMap, PrepareError> {
-    // Generate label map
-    let mut label_map = LabelMap::new();
-    examples
+    let classes = examples
         .iter()
-        .flat_map(|e| e.objects.iter())
-        .for_each(|o| {
-            label_map.add(&o.name);
-        });
+        .flat_map(|e| e.objects.iter().map(|o| &o.name));
+    let mut label_map = match &opts.label_map {
+        Some(label_map) => {
+            let unknown: BTreeSet<&String> =
+                classes.filter(|c| label_map.get(c).is_none()).collect();
+            if !unknown.is_empty() {
+                return Err(PrepareError::UnknownClasses(
+                    unknown.into_iter().cloned().collect(),
+                ));
+            }
+            label_map.clone()
+        }
+        None => {
+            let mut label_map = LabelMap::new();
+            classes.for_each(|c| {
+                label_map.add(c);
+            });
+            label_map
+        }
+    };
+
+    if let Some(definitions) = &opts.classes {
+        let unknown = label_map.define(definitions);
+        if !unknown.is_empty() {
+            warn!("Classes {:?} are defined but not in the label map", unknown);
+        }
+    }
 
     // Write label map to file
-    let mut label_output: PathBuf = opts.output.clone();
-    label_output.push("label_map.txt");
+    let label_output = opts.output.join(LABEL_MAP_FILENAME);
     label_map.clone().write_to_file(&label_output)?;
 
     Ok(label_map)
 }
 
-// Generate the label map and write it to a file
+// Generate a tfrecord file from a set of examples
 fn gen_tfrecord(
     opts: &PrepareOpts,
     examples: Vec<Annotation>,
     label_map: &LabelMap,
-    filename: &str,
-) -> Result<Option<()>, PrepareError> {
+    name: &str,
+    split: &mut SplitReport,
+    context: &mut WriteContext,
+) -> Result<(), PrepareError> {
     if examples.is_empty() {
-        warn!("{} dataset is empty, tfrecord won't be generated", filename);
-        return Ok(None);
+        warn!("{} dataset is empty, tfrecord won't be generated", name);
+        return Ok(());
     }
 
-    let mut record = RecordBuilder::new(0, label_map.clone());
-    examples.into_iter().for_each(|e| record.add_example(e));
+    let mut record = record_builder(opts, label_map);
+    examples.into_iter().for_each(|e| {
+        let path = e.system_path.clone();
+        let size = record.size();
+        let added = record.add_example(e);
+        context.written.add(record.size() - size);
+        match added {
+            Ok(Some(transcoding)) => {
+                split.examples += 1;
+                context.report.transcoded.push((path, transcoding));
+            }
+            Ok(None) => split.examples += 1,
+            Err(e) => context.report.dropped_examples.push((path, e)),
+        }
+    });
 
     // Write tfrecord
-    let mut record_output: PathBuf = opts.output.clone();
-    record_output.push(filename);
-    record.write_tfrecord(&record_output)?;
+    split.files = record.write_tfrecord(&opts.output, name, opts.shards)?;
+    info!("{} example(s) written to {:?}", split.examples, split.files);
+
+    Ok(())
+}
+
+// Initialize a record builder with the output options
+fn record_builder(opts: &PrepareOpts, label_map: &LabelMap) -> RecordBuilder {
+    RecordBuilder::new(0, label_map.clone())
+        .transcode_to(opts.image_encoding)
+        .with_provenance(opts.provenance)
+        .with_schema(opts.schema.clone())
+}
+
+// State shared by the functions writing the sets: the progress of the examples written, and the
+// report they fill in
+struct WriteContext<'a> {
+    written: WriteProgress,
+    report: &'a mut Report,
+}
+
+// Progress of the examples written to tfrecord files, and of their size
+struct WriteProgress {
+    bar: ProgressBar,
+    bytes: usize,
+}
+
+impl WriteProgress {
+    fn new(bar: ProgressBar) -> WriteProgress {
+        WriteProgress { bar, bytes: 0 }
+    }
 
-    Ok(Some(()))
+    // An example was processed, `bytes` is the size of the serialized example
+    fn add(&mut self, bytes: usize) {
+        self.bytes += bytes;
+        self.bar.inc(1);
+        self.bar
+            .set_message(&format!("{} written", HumanBytes(self.bytes as u64)));
+    }
 }
 
 // Recursively walk the specified root directory and return XML paths
-fn get_xml_paths(root: &Path) -> Vec<PathBuf> {
+pub fn get_xml_paths(root: &Path) -> Vec<PathBuf> {
+    scan_xml_paths(root, &ProgressBar::hidden())
+}
+
+// Same as `get_xml_paths`, each file that is walked through ticks the progress bar
+fn scan_xml_paths(root: &Path, scanned: &ProgressBar) -> Vec<PathBuf> {
     WalkDir::new(root)
         .into_iter()
         .filter_map(|e| e.ok())
+        .inspect(|_| scanned.inc(1))
         .filter_map(|e| {
             e.path()
                 .extension()
@@ -139,9 +728,38 @@ fn get_xml_paths(root: &Path) -> Vec<PathBuf> {
 pub struct Report {
     pub valid_annotations: usize,
     pub invalid_annotations: Vec<(PathBuf, PascalVocError)>,
-    pub train: bool,
-    pub test: bool,
-    pub labels: bool,
+    pub transcoded: Vec<(PathBuf, Transcoding)>,
+    pub dropped_examples: Vec<(PathBuf, TfRecordError)>,
+    pub excluded_difficult: usize,
+    /// Classes renamed and objects left out by the label rules
+    pub labels: LabelMapping,
+    pub splits: Vec<SplitReport>,
+    /// Path of the label map, None if it was not written
+    pub label_map: Option<PathBuf>,
+    /// Directory of the split manifest
+    pub manifest: Option<PathBuf>,
+    /// Number of examples assigned by the manifest passed with `from_manifest`
+    pub from_manifest: usize,
+    /// Clusters of duplicate images, by annotation path
+    pub duplicates: Vec<Vec<PathBuf>>,
+    /// Number of duplicates left out of the dataset
+    pub dropped_duplicates: usize,
+    /// Path of the HTML report
+    pub html_report: Option<PathBuf>,
+}
+
+/// What was written for each set
+#[derive(Debug, Default)]
+pub struct SplitReport {
+    pub name: String,
+    /// Paths of the tfrecord files, one per shard. Empty if the set was empty.
+    pub files: Vec<PathBuf>,
+    /// Number of examples written to the tfrecord file
+    pub examples: usize,
+    /// Number of images containing each class
+    pub class_counts: BTreeMap<String, usize>,
+    /// Annotation paths of the examples shown in the HTML report
+    pub samples: Vec<PathBuf>,
 }
 
 impl Report {
@@ -149,17 +767,96 @@ impl Report {
         self.valid_annotations + self.invalid_annotations.len()
     }
 
-    pub fn print_report(&self) {
-        match (self.test, self.train) {
-            (true, true) => println!(
-                "Done, 3 files were written, found {} examples.",
-                self.total_examples()
-            ),
-            (_, true) | (true, _) => println!(
-                "Done, 2 files were written, one dataset was empty, found {} examples.",
+    /// Conditions of `fail_on` that are met, in the order they were given
+    pub fn failures(&self, fail_on: &[FailOn]) -> Vec<FailOn> {
+        fail_on
+            .iter()
+            .copied()
+            .filter(|condition| match condition {
+                FailOn::Invalid => !self.invalid_annotations.is_empty(),
+                FailOn::EmptySplit => self.splits.iter().any(|s| s.files.is_empty()),
+                FailOn::Dropped => !self.dropped_examples.is_empty(),
+            })
+            .collect()
+    }
+
+    pub fn print_report(&self, format: ReportFormat) -> Result<(), serde_json::Error> {
+        match format {
+            ReportFormat::Text => self.print_text(),
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&self.to_json())?),
+        }
+
+        Ok(())
+    }
+
+    fn to_json(&self) -> JsonReport<'_> {
+        JsonReport {
+            examples: self.total_examples(),
+            valid_annotations: self.valid_annotations,
+            invalid_annotations: self
+                .invalid_annotations
+                .iter()
+                .map(|(path, e)| JsonError::new(path, e))
+                .collect(),
+            dropped_examples: self
+                .dropped_examples
+                .iter()
+                .map(|(path, e)| JsonError::new(path, e))
+                .collect(),
+            transcoded: self
+                .transcoded
+                .iter()
+                .map(|(path, t)| JsonTranscoding {
+                    path,
+                    from: &t.from,
+                    to: t.to.name(),
+                })
+                .collect(),
+            excluded_difficult: self.excluded_difficult,
+            labels: &self.labels,
+            label_map: self.label_map.as_deref().map(JsonFile::new),
+            splits: self
+                .splits
+                .iter()
+                .map(|split| JsonSplit {
+                    name: &split.name,
+                    examples: split.examples,
+                    class_counts: &split.class_counts,
+                    files: split.files.iter().map(|f| JsonFile::new(f)).collect(),
+                })
+                .collect(),
+            manifest: self.manifest.as_deref(),
+            from_manifest: self.from_manifest,
+            duplicates: &self.duplicates,
+            dropped_duplicates: self.dropped_duplicates,
+            html_report: self.html_report.as_deref().map(JsonFile::new),
+        }
+    }
+
+    fn print_text(&self) {
+        if self.label_map.is_some() {
+            let written: usize = self.splits.iter().map(|s| s.files.len()).sum();
+            println!(
+                "Done, {} files were written, found {} examples.",
+                written + 1,
                 self.total_examples()
-            ),
-            _ => (),
+            );
+            self.splits
+                .iter()
+                .for_each(|split| match split.files.as_slice() {
+                    [] => println!("   - {}: empty, no tfrecord was written", split.name),
+                    [path] => println!(
+                        "   - {}: {} example(s) in {:?}",
+                        split.name, split.examples, path
+                    ),
+                    files => println!(
+                        "   - {}: {} example(s) in {} shards, {:?}...",
+                        split.name,
+                        split.examples,
+                        files.len(),
+                        files[0]
+                    ),
+                });
         }
 
         if !self.invalid_annotations.is_empty() {
@@ -171,7 +868,178 @@ impl Report {
                 println!("   - In {:?} - {}", e.0, e.1);
             })
         }
+
+        if let Some(manifest) = &self.manifest {
+            println!("Split manifest written to {:?}.", manifest);
+        }
+
+        if let Some(path) = &self.html_report {
+            println!("HTML report written to {:?}.", path);
+        }
+
+        if self.from_manifest > 0 {
+            println!(
+                "{} example(s) kept the set assigned by the input manifest.",
+                self.from_manifest
+            );
+        }
+
+        let classes: BTreeSet<&String> = self
+            .splits
+            .iter()
+            .flat_map(|s| s.class_counts.keys())
+            .collect();
+        if !classes.is_empty() {
+            let names: Vec<&str> = self.splits.iter().map(|s| s.name.as_str()).collect();
+            println!("Images per class ({}):", names.join(" / "));
+            classes.into_iter().for_each(|class| {
+                let counts: Vec<String> = self
+                    .splits
+                    .iter()
+                    .map(|s| s.class_counts.get(class).copied().unwrap_or(0).to_string())
+                    .collect();
+                println!("   - {}: {}", class, counts.join(" / "));
+            })
+        }
+
+        if !self.labels.renamed.is_empty() {
+            println!("{} class(es) were renamed:", self.labels.renamed.len());
+            self.labels.renamed.iter().for_each(|(from, renamed)| {
+                println!(
+                    "   - {} -> {}: {} object(s)",
+                    from, renamed.to, renamed.objects
+                );
+            })
+        }
+
+        if !self.labels.dropped.is_empty() {
+            let dropped: usize = self.labels.dropped.values().sum();
+            println!("{} object(s) were left out by the label rules:", dropped);
+            self.labels.dropped.iter().for_each(|(class, count)| {
+                println!("   - {}: {} object(s)", class, count);
+            })
+        }
+
+        if !self.duplicates.is_empty() {
+            println!("{} cluster(s) of duplicate images:", self.duplicates.len());
+            self.duplicates.iter().for_each(|cluster| {
+                let paths: Vec<String> = cluster.iter().map(|p| format!("{:?}", p)).collect();
+                println!("   - {}", paths.join(", "));
+            });
+            if self.dropped_duplicates > 0 {
+                println!(
+                    "{} duplicate(s) were left out, the first example of each cluster was kept.",
+                    self.dropped_duplicates
+                );
+            }
+        }
+
+        if self.excluded_difficult > 0 {
+            println!(
+                "{} difficult object(s) were left out of the train set.",
+                self.excluded_difficult
+            );
+        }
+
+        if !self.transcoded.is_empty() {
+            println!("{} image(s) were transcoded:", self.transcoded.len());
+            self.transcoded.iter().for_each(|(path, t)| {
+                println!("   - {:?} - {} to {}", path, t.from, t.to.name());
+            })
+        }
+
+        if !self.dropped_examples.is_empty() {
+            println!(
+                "{} example(s) were left out of the tfrecord files:",
+                self.dropped_examples.len(),
+            );
+            self.dropped_examples.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+    }
+}
+
+// JSON output, errors are included with their causes
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    examples: usize,
+    valid_annotations: usize,
+    invalid_annotations: Vec<JsonError<'a>>,
+    dropped_examples: Vec<JsonError<'a>>,
+    transcoded: Vec<JsonTranscoding<'a>>,
+    excluded_difficult: usize,
+    labels: &'a LabelMapping,
+    label_map: Option<JsonFile<'a>>,
+    splits: Vec<JsonSplit<'a>>,
+    manifest: Option<&'a Path>,
+    from_manifest: usize,
+    duplicates: &'a [Vec<PathBuf>],
+    dropped_duplicates: usize,
+    html_report: Option<JsonFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSplit<'a> {
+    name: &'a str,
+    examples: usize,
+    class_counts: &'a BTreeMap<String, usize>,
+    /// One file per shard, empty if the set was empty
+    files: Vec<JsonFile<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonFile<'a> {
+    path: &'a Path,
+    /// Size in bytes, None if the file can't be read anymore
+    size: Option<u64>,
+}
+
+impl<'a> JsonFile<'a> {
+    fn new(path: &'a Path) -> Self {
+        JsonFile {
+            path,
+            size: fs::metadata(path).ok().map(|m| m.len()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonError<'a> {
+    path: &'a Path,
+    error: String,
+    /// Underlying errors, from the closest to the root cause
+    causes: Vec<String>,
+}
+
+impl<'a> JsonError<'a> {
+    fn new(path: &'a Path, e: &dyn Error) -> Self {
+        let mut chain = error_chain(e);
+        let error = chain.remove(0);
+        JsonError {
+            path,
+            error,
+            causes: chain,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonTranscoding<'a> {
+    path: &'a Path,
+    from: &'a str,
+    to: &'static str,
+}
+
+/// Messages of an error and of its causes, the error itself comes first
+pub fn error_chain(e: &dyn Error) -> Vec<String> {
+    let mut chain = vec![e.to_string()];
+    let mut source = e.source();
+    while let Some(cause) = source {
+        chain.push(cause.to_string());
+        source = cause.source();
     }
+    chain
 }
 
 #[derive(Debug, Error)]
@@ -184,4 +1052,211 @@ pub enum PrepareError {
 
     #[error("Something went wrong while generating tfrecord file")]
     TfRecord(#[from] TfRecordError),
+
+    #[error("Something went wrong with the split manifest")]
+    Manifest(#[from] ManifestError),
+
+    #[error("Classes {0:?} are missing from the label map, map them with label rules")]
+    UnknownClasses(Vec<String>),
+
+    #[error(
+        "Difficult objects are excluded from the training sets {0:?}, but no set has these names"
+    )]
+    NoTrainingSet(Vec<String>),
+}
+
+#[test]
+fn test_failures() {
+    let mut report = Report::default();
+    report.splits.push(SplitReport {
+        name: "test".to_owned(),
+        ..Default::default()
+    });
+    report.invalid_annotations.push((
+        PathBuf::from("missing.xml"),
+        Annotation::from_file(Path::new("./dataset/missing.xml")).unwrap_err(),
+    ));
+
+    let fail_on = [FailOn::Dropped, FailOn::EmptySplit, FailOn::Invalid];
+    assert_eq!(
+        report.failures(&fail_on),
+        vec![FailOn::EmptySplit, FailOn::Invalid]
+    );
+    assert!(report.failures(&[FailOn::Dropped]).is_empty());
+
+    let json = serde_json::to_value(report.to_json()).unwrap();
+    assert_eq!(json["splits"][0]["files"], serde_json::json!([]));
+    let error = &json["invalid_annotations"][0];
+    assert_eq!(
+        error["error"],
+        "Io error while attempting to read the example"
+    );
+    assert_eq!(error["causes"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn test_assign_from_manifest() {
+    use crate::pascal_voc::split::GroupBy;
+
+    let root = Path::new("./dataset");
+    let examples: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&root.join(format!("{}.xml", i))).unwrap())
+        .collect();
+    let names = ["train", "test"];
+    let listed = |train: &[usize], test: &[usize]| {
+        let set = |indices: &[usize]| indices.iter().map(|i| examples[*i].clone()).collect();
+        Manifest::new(root, &names, &[set(train), set(test)])
+    };
+
+    // 1.xml and 2.xml are duplicates, 3.xml isn't listed
+    let mut split = SplitOpts::default();
+    split
+        .clusters
+        .insert(examples[0].annotation_path.clone(), 0);
+    split
+        .clusters
+        .insert(examples[2].annotation_path.clone(), 0);
+
+    let manifest = listed(&[0], &[1]);
+    let assigned = assign_from_manifest(root, examples.clone(), &names, &manifest, &split).unwrap();
+    assert_eq!(assigned.count, 2);
+    let filenames: Vec<&str> = assigned.listed[0]
+        .iter()
+        .map(|e| e.filename.as_str())
+        .collect();
+    assert_eq!(filenames, vec!["1.jpg", "3.jpg"]);
+    assert_eq!(assigned.remaining.len(), 3);
+
+    // Duplicates listed in different sets
+    split
+        .clusters
+        .insert(examples[1].annotation_path.clone(), 0);
+    assert!(matches!(
+        assign_from_manifest(root, examples.clone(), &names, &manifest, &split),
+        Err(ManifestError::Separated(..))
+    ));
+
+    // New images of a group follow its listed images, all images are in the same directory
+    let grouped = SplitOpts {
+        group_by: Some(GroupBy::Directory),
+        ..Default::default()
+    };
+    let manifest = listed(&[0, 1], &[]);
+    let assigned =
+        assign_from_manifest(root, examples.clone(), &names, &manifest, &grouped).unwrap();
+    assert_eq!(assigned.listed[0].len(), 6);
+    assert!(assigned.remaining.is_empty());
+    let manifest = listed(&[0], &[1]);
+    assert!(assign_from_manifest(root, examples, &names, &manifest, &grouped).is_err());
+}
+
+#[test]
+fn test_exclude_difficult() {
+    use crate::pascal_voc::tfrecord::read_records;
+    use crate::tensorflow_protos::example::Example;
+
+    let directory = std::env::temp_dir().join("tftools-test-exclude-difficult");
+    let opts = |folds| PrepareOpts {
+        input: PathBuf::from("./dataset"),
+        output: directory.clone(),
+        splits: vec![SplitSpec::new("train", 1f64), SplitSpec::new("test", 1f64)],
+        split: SplitOpts::default(),
+        folds,
+        from_manifest: None,
+        label_rules: None,
+        label_map: None,
+        classes: None,
+        duplicates: None,
+        shards: 1,
+        image_encoding: ImageEncoding::Jpeg,
+        exclude_difficult: true,
+        training_sets: vec!["train".to_owned()],
+        provenance: false,
+        schema: Schema::default(),
+        html_report: false,
+        report_format: ReportFormat::Text,
+        fail_on: Vec::new(),
+        progress: false,
+    };
+    // The first object of each image is difficult
+    let examples: Vec<Annotation> = get_xml_paths(Path::new("./dataset"))
+        .iter()
+        .map(|path| {
+            let mut example = Annotation::from_file(path).unwrap();
+            example.objects[0].difficult = true;
+            example
+        })
+        .collect();
+    let run = |opts: PrepareOpts| {
+        fs::create_dir_all(&opts.output).unwrap();
+        let label_map = gen_label_map(&opts, &examples).unwrap();
+        let mut report = Report::default();
+        let mut context = WriteContext {
+            written: WriteProgress::new(ProgressBar::hidden()),
+            report: &mut report,
+        };
+        match opts.folds {
+            Some(folds) => gen_folds(&opts, examples.clone(), &label_map, folds, &mut context),
+            None => gen_splits(&opts, examples.clone(), &label_map, &mut context),
+        }
+        .unwrap();
+        report
+    };
+    // Difficult flags of the objects written to a set
+    let difficult = |split: &SplitReport| -> Vec<i64> {
+        split
+            .files
+            .iter()
+            .flat_map(|path| read_records(path).unwrap())
+            .flat_map(|bytes| {
+                let example: Example = protobuf::parse_from_bytes(&bytes).unwrap();
+                example.get_features().get_feature()["image/object/difficult"]
+                    .get_int64_list()
+                    .get_value()
+                    .to_vec()
+            })
+            .collect()
+    };
+
+    // Difficult objects are left out of the training set only
+    let report = run(opts(None));
+    let (train, test) = (&report.splits[0], &report.splits[1]);
+    assert_eq!(report.excluded_difficult, train.examples);
+    assert!(!difficult(train).contains(&1));
+    assert_eq!(
+        difficult(test).iter().filter(|flag| **flag == 1).count(),
+        test.examples
+    );
+
+    // Each fold keeps them in its test set, and leaves them out of its training set
+    let report = run(opts(Some(2)));
+    assert_eq!(report.excluded_difficult, examples.len());
+    assert_eq!(report.splits.len(), 4);
+    for split in &report.splits {
+        let flags = difficult(split);
+        assert!(split.examples > 0);
+        if split.name.ends_with("/train") {
+            assert!(!flags.contains(&1));
+        } else {
+            assert_eq!(
+                flags.iter().filter(|flag| **flag == 1).count(),
+                split.examples
+            );
+        }
+    }
+
+    // Training sets with other names are named in the options, the run fails when none matches
+    let mut named = opts(None);
+    named.splits[0].name = "training".to_owned();
+    assert!(matches!(
+        prepare(named),
+        Err(PrepareError::NoTrainingSet(_))
+    ));
+    let mut named = opts(None);
+    named.splits[0].name = "training".to_owned();
+    named.training_sets = vec!["training".to_owned()];
+    let report = run(named);
+    assert!(report.excluded_difficult > 0);
+    assert!(!difficult(&report.splits[0]).contains(&1));
+    fs::remove_dir_all(&directory).unwrap();
 }
@@ -0,0 +1,278 @@
+//! This module implements annotated previews of a PASCAL-VOC dataset or of a tfrecord file.
+//! - Parse PASCAL-VOC files, or decode examples from a tfrecord file
+//! - Optionally keep a sample of the examples
+//! - Draw boxes and class names onto each image and save it to the output directory
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::{Path, PathBuf};
+
+use image::{ImageError, RgbImage};
+use rusttype::Font;
+use thiserror::Error;
+
+use super::prepare::get_xml_paths;
+use crate::math::{self, BBox};
+use crate::pascal_voc::draw::{default_font, draw_boxes};
+use crate::pascal_voc::manifest::example_id;
+use crate::pascal_voc::parser::{Annotation, PascalVocError};
+use crate::pascal_voc::schema::{BoxUnits, Schema};
+use crate::pascal_voc::tfrecord::{decode_example, RecordReader, TfRecordError};
+
+/// Where examples are read from
+#[derive(Debug)]
+pub enum PreviewSource {
+    /// A directory of PASCAL-VOC files, searched recursively
+    Dataset(PathBuf),
+    /// A tfrecord file, features are read with the keys of the schema
+    TfRecord(PathBuf, Box<Schema>),
+}
+
+/// Configuration options for rendering annotated previews
+#[derive(Debug)]
+pub struct PreviewOpts {
+    pub source: PreviewSource,
+    /// Output directory, where previews will be written.
+    pub output: PathBuf,
+    /// Only render this many examples. The sample is the same between runs.
+    pub sample: Option<usize>,
+}
+
+// Renders previews of a dataset or a tfrecord file into the output directory
+pub fn preview(opts: PreviewOpts) -> Result<Report, PreviewError> {
+    fs::create_dir_all(&opts.output)?;
+    let font = default_font().ok_or(PreviewError::Font)?;
+    let mut report = Report::default();
+
+    match &opts.source {
+        PreviewSource::Dataset(input) => preview_dataset(&opts, input, &font, &mut report),
+        PreviewSource::TfRecord(path, schema) => {
+            preview_tfrecord(&opts, path, schema, &font, &mut report)?
+        }
+    }
+
+    Ok(report)
+}
+
+// Previews of PASCAL-VOC files, named after their path relative to the input directory
+fn preview_dataset(opts: &PreviewOpts, input: &Path, font: &Font, report: &mut Report) {
+    let mut examples = Vec::new();
+    get_xml_paths(input)
+        .iter()
+        .for_each(|path| match Annotation::from_file(path) {
+            Ok(annotation) => examples.push(annotation),
+            Err(e) => report.invalid_annotations.push((path.to_owned(), e)),
+        });
+
+    let examples = math::sample(examples, opts.sample, |e| {
+        math::seeded_hash(e.annotation_path.to_string_lossy().as_bytes(), None)
+    });
+    examples.into_iter().for_each(|example| {
+        let output = opts
+            .output
+            .join(format!("{}.jpg", example_id(input, &example)));
+        let boxes: Vec<(BBox, String)> = example
+            .objects
+            .iter()
+            .map(|o| (BBox::from(&o.bndbox), o.name.clone()))
+            .collect();
+
+        let result = image::open(&example.system_path)
+            .map_err(PreviewError::from)
+            .and_then(|image| save_preview(image.to_rgb8(), &boxes, font, &output));
+        match result {
+            Ok(()) => report.written.push(output),
+            Err(e) => report.failed.push((example.system_path.clone(), e)),
+        }
+    });
+}
+
+// Previews of the examples of a tfrecord file, named after their index and file name.
+// Records are streamed, only the sample is held in memory.
+fn preview_tfrecord(
+    opts: &PreviewOpts,
+    path: &Path,
+    schema: &Schema,
+    font: &Font,
+    report: &mut Report,
+) -> Result<(), PreviewError> {
+    let mut render =
+        |index: usize, bytes: &[u8]| match preview_record(opts, schema, font, index, bytes) {
+            Ok(output) => report.written.push(output),
+            Err(e) => report
+                .failed
+                .push((PathBuf::from(format!("record {}", index)), e)),
+        };
+
+    let records = RecordReader::open(path)?.enumerate();
+    let count = match opts.sample {
+        Some(count) => count,
+        None => {
+            for (index, record) in records {
+                render(index, &record?);
+            }
+            return Ok(());
+        }
+    };
+
+    // Keep the records with the smallest hashes, the same sample as `math::sample`
+    let mut sample: BinaryHeap<(u32, usize, Vec<u8>)> = BinaryHeap::with_capacity(count);
+    for (index, record) in records {
+        let bytes = record?;
+        let hash = math::seeded_hash(&bytes, None);
+        if sample.len() < count {
+            sample.push((hash, index, bytes));
+        } else if let Some((largest, last, _)) = sample.peek() {
+            if (hash, index) < (*largest, *last) {
+                sample.pop();
+                sample.push((hash, index, bytes));
+            }
+        }
+    }
+    let mut sample = sample.into_vec();
+    sample.sort_by_key(|(_, index, _)| *index);
+    sample
+        .iter()
+        .for_each(|(_, index, bytes)| render(*index, bytes));
+
+    Ok(())
+}
+
+// Decode a record and save its preview
+fn preview_record(
+    opts: &PreviewOpts,
+    schema: &Schema,
+    font: &Font,
+    index: usize,
+    bytes: &[u8],
+) -> Result<PathBuf, PreviewError> {
+    let example = decode_example(bytes, schema)?;
+    let image = image::load_from_memory(&example.image)?.to_rgb8();
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let units = example.units;
+    let boxes: Vec<(BBox, String)> = example
+        .boxes
+        .iter()
+        .map(|b| match units {
+            BoxUnits::Normalized => b.denormalize(width, height),
+            BoxUnits::Absolute => *b,
+        })
+        .zip(example.classes)
+        .collect();
+
+    let stem = example
+        .filename
+        .as_deref()
+        .and_then(|f| Path::new(f).file_stem())
+        .map(|f| format!("-{}", f.to_string_lossy()))
+        .unwrap_or_default();
+    let output = opts.output.join(format!("{:05}{}.jpg", index, stem));
+    save_preview(image, &boxes, font, &output)?;
+
+    Ok(output)
+}
+
+// Draw boxes onto an image and save it, parent directories are created if needed
+fn save_preview(
+    mut image: RgbImage,
+    boxes: &[(BBox, String)],
+    font: &Font,
+    output: &Path,
+) -> Result<(), PreviewError> {
+    draw_boxes(&mut image, boxes, font);
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    image.save(output)?;
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub invalid_annotations: Vec<(PathBuf, PascalVocError)>,
+    /// Paths of the previews
+    pub written: Vec<PathBuf>,
+    /// Images or records that could not be rendered
+    pub failed: Vec<(PathBuf, PreviewError)>,
+}
+
+impl Report {
+    pub fn print_report(&self) {
+        println!("Done, {} preview(s) were written.", self.written.len());
+
+        if !self.invalid_annotations.is_empty() {
+            println!(
+                "{} example(s) could not be processed:",
+                self.invalid_annotations.len(),
+            );
+            self.invalid_annotations.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+
+        if !self.failed.is_empty() {
+            println!("{} preview(s) could not be rendered:", self.failed.len());
+            self.failed.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum PreviewError {
+    #[error("An IO error occured while rendering previews")]
+    IoError(#[from] IoError),
+
+    #[error("Could not read the tfrecord file")]
+    TfRecord(#[from] TfRecordError),
+
+    #[error("Could not decode or encode the image")]
+    Image(#[from] ImageError),
+
+    #[error("Could not load the font used for class names")]
+    Font,
+}
+
+#[test]
+fn test_preview_tfrecord() {
+    use crate::pascal_voc::label_map::LabelMap;
+    use crate::pascal_voc::tfrecord::{write_records, RecordBuilder};
+
+    let directory = std::env::temp_dir().join("tftools-test-preview");
+    fs::create_dir_all(&directory).unwrap();
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+    let record = RecordBuilder::new(0, label_map);
+    let records: Vec<Vec<u8>> = get_xml_paths(Path::new("./dataset"))
+        .iter()
+        .map(|path| Annotation::from_file(path).unwrap())
+        .map(|example| record.encode_example(example).unwrap().bytes)
+        .collect();
+    let path = directory.join("input.tfrecord");
+    write_records(&path, records.iter().map(Vec::as_slice)).unwrap();
+
+    // The streamed sample is the one of `math::sample`
+    let report = preview(PreviewOpts {
+        source: PreviewSource::TfRecord(path, Box::default()),
+        output: directory.join("previews"),
+        sample: Some(2),
+    })
+    .unwrap();
+    let indices: Vec<usize> = (0..records.len()).collect();
+    let expected = math::sample(indices, Some(2), |i| math::seeded_hash(&records[*i], None));
+    let written: Vec<usize> = report
+        .written
+        .iter()
+        .map(|path| {
+            path.file_name().unwrap().to_string_lossy()[..5]
+                .parse()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(written, expected);
+    assert!(report.failed.is_empty());
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -0,0 +1,307 @@
+//! This module rewrites existing tfrecord files into a new set of shards.
+//! - Read the records of each input, one at a time, so inputs can be larger than memory
+//! - Leave out the records that don't satisfy every filter
+//! - Optionally shuffle them: inputs are interleaved in proportion to the bytes they have left,
+//!   and records are mixed through a buffer of a bounded number of records
+//! - Write each record to the smallest shard so far, shards end up with similar sizes
+use std::fs::{self, File};
+use std::io::{BufReader, Error as IoError};
+use std::mem;
+use std::path::PathBuf;
+
+use indicatif::HumanBytes;
+use thiserror::Error;
+
+use crate::math::Rng;
+use crate::pascal_voc::predicate::{matches_all, Predicate};
+use crate::pascal_voc::tfrecord::{shard_path, RecordReader, Shard, ShardWriter, TfRecordError};
+use crate::progress::Progress;
+
+/// Configuration options for resharding tfrecord files
+#[derive(Debug)]
+pub struct ReshardOpts {
+    pub inputs: Vec<PathBuf>,
+    /// Output directory
+    pub output: PathBuf,
+    /// Name of the output shards, `<name>-00000-of-00004.tfrecord`
+    pub name: String,
+    pub shards: usize,
+    /// Records are kept in their order when missing
+    pub shuffle: Option<Shuffle>,
+    /// Records are kept when they satisfy all the filters
+    pub filters: Vec<Predicate>,
+    /// Show progress on stderr
+    pub progress: bool,
+}
+
+/// Seeded shuffle, the same seed and inputs give the same shards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shuffle {
+    pub seed: u64,
+    /// Number of records held in memory. Records can only move within the buffer, a larger
+    /// buffer gives a more uniform shuffle. Records are held whole, images included: 10000
+    /// image records take several GB.
+    pub buffer: usize,
+}
+
+// Length, CRC of the length and CRC of the data around each record
+const FRAMING: usize = 16;
+
+// An input being read, with the number of records read so far and the number of bytes left
+struct Input {
+    path: PathBuf,
+    reader: RecordReader<BufReader<File>>,
+    records: usize,
+    remaining: usize,
+}
+
+// Read the records of the inputs, filter and shuffle them, and write them to the new shards
+pub fn reshard(opts: ReshardOpts) -> Result<Report, ReshardError> {
+    check_outputs(&opts)?;
+    let mut inputs = opts
+        .inputs
+        .iter()
+        .map(|path| {
+            let read = |e| ReshardError::Read(path.to_owned(), e);
+            let reader = RecordReader::open(path).map_err(read)?;
+            let length = fs::metadata(path).map_err(|e| read(e.into()))?.len();
+            Ok(Input {
+                path: path.to_owned(),
+                reader,
+                records: 0,
+                remaining: length as usize,
+            })
+        })
+        .collect::<Result<Vec<Input>, ReshardError>>()?;
+
+    fs::create_dir_all(&opts.output)?;
+    let mut writer = ShardWriter::create(&opts.output, &opts.name, opts.shards)?;
+    let mut rng = opts.shuffle.map(|shuffle| Rng::new(shuffle.seed));
+    let capacity = opts.shuffle.map_or(1, |shuffle| shuffle.buffer.max(1));
+    let mut buffer: Vec<Vec<u8>> = Vec::new();
+    let mut report = Report {
+        inputs: opts.inputs.len(),
+        shuffle: opts.shuffle,
+        ..Default::default()
+    };
+    let progress = Progress::new(opts.progress).spinner("Reading records");
+
+    while !inputs.is_empty() {
+        // Inputs are read one after the other, or interleaved at random when shuffling
+        let index = match &mut rng {
+            Some(rng) => pick_input(&inputs, rng),
+            None => 0,
+        };
+        let input = &mut inputs[index];
+        let record = match input.reader.next() {
+            Some(record) => record.map_err(|e| ReshardError::Read(input.path.clone(), e))?,
+            None => {
+                inputs.remove(index);
+                continue;
+            }
+        };
+        input.records += 1;
+        input.remaining = input.remaining.saturating_sub(record.len() + FRAMING);
+        report.read += 1;
+        progress.inc(1);
+
+        let kept = matches_all(&opts.filters, &record)
+            .map_err(|e| ReshardError::Decode(input.path.clone(), input.records - 1, e))?;
+        if !kept {
+            report.filtered += 1;
+            continue;
+        }
+
+        // A full buffer swaps the new record with a random one, which is written
+        match &mut rng {
+            Some(rng) if buffer.len() >= capacity => {
+                let chosen = rng.below(buffer.len());
+                let record = mem::replace(&mut buffer[chosen], record);
+                writer.write(&record)?;
+            }
+            Some(_) => buffer.push(record),
+            None => writer.write(&record)?,
+        }
+    }
+
+    if let Some(rng) = &mut rng {
+        rng.shuffle(&mut buffer);
+    }
+    for record in buffer {
+        writer.write(&record)?;
+    }
+    progress.finish_and_clear();
+    report.shards = writer.finish()?;
+
+    Ok(report)
+}
+
+// Pick the input to read from, in proportion to the bytes it has left. Otherwise the records of a
+// small input would all be read early, and end up at the beginning of the shards.
+fn pick_input(inputs: &[Input], rng: &mut Rng) -> usize {
+    let total: usize = inputs.iter().map(|input| input.remaining).sum();
+    if total == 0 {
+        // Every byte was read, the readers only have to reach their end
+        return 0;
+    }
+    let mut target = rng.below(total);
+    inputs
+        .iter()
+        .position(|input| match target.checked_sub(input.remaining) {
+            Some(rest) => {
+                target = rest;
+                false
+            }
+            None => true,
+        })
+        .unwrap_or(0)
+}
+
+// The outputs are truncated when they are created, they can't be one of the inputs
+fn check_outputs(opts: &ReshardOpts) -> Result<(), ReshardError> {
+    let inputs: Vec<PathBuf> = opts
+        .inputs
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+    let shards = opts.shards.max(1);
+    let overwritten = (0..shards)
+        .map(|index| shard_path(&opts.output, &opts.name, index, shards))
+        .find(|path| match path.canonicalize() {
+            Ok(path) => inputs.contains(&path),
+            Err(_) => false,
+        });
+    match overwritten {
+        Some(path) => Err(ReshardError::Overwrite(path)),
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Number of input files
+    pub inputs: usize,
+    /// Number of records read
+    pub read: usize,
+    /// Number of records left out by the filters
+    pub filtered: usize,
+    pub shuffle: Option<Shuffle>,
+    pub shards: Vec<Shard>,
+}
+
+impl Report {
+    /// Number of records written to the shards
+    pub fn written(&self) -> usize {
+        self.shards.iter().map(|shard| shard.records).sum()
+    }
+
+    pub fn print_report(&self) {
+        println!(
+            "Read {} record(s) from {} file(s), {} left out by the filters.",
+            self.read, self.inputs, self.filtered
+        );
+        if let Some(shuffle) = &self.shuffle {
+            println!(
+                "Records were shuffled with seed {} and a buffer of {} records.",
+                shuffle.seed, shuffle.buffer
+            );
+        }
+        println!(
+            "Wrote {} record(s) to {} shard(s):",
+            self.written(),
+            self.shards.len()
+        );
+        self.shards.iter().for_each(|shard| {
+            println!(
+                "   - {:?}: {} record(s), {}",
+                shard.path,
+                shard.records,
+                HumanBytes(shard.bytes as u64)
+            );
+        });
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReshardError {
+    #[error("Could not read {0:?}")]
+    Read(PathBuf, #[source] TfRecordError),
+
+    #[error("Could not decode record {1} of {0:?} to filter it")]
+    Decode(PathBuf, usize, #[source] TfRecordError),
+
+    #[error("Could not write the shards")]
+    Write(#[from] TfRecordError),
+
+    #[error("The shard {0:?} would overwrite one of the inputs")]
+    Overwrite(PathBuf),
+
+    #[error("An IO error occured while creating the output directory")]
+    IoError(#[from] IoError),
+}
+
+#[test]
+fn test_reshard() {
+    use crate::pascal_voc::tfrecord::{read_records, write_records};
+
+    let directory = std::env::temp_dir().join("tftools-test-reshard");
+    fs::create_dir_all(&directory).unwrap();
+    let input = directory.join("input.tfrecord");
+    let records: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; usize::from(i % 7) + 1]).collect();
+    write_records(&input, records.iter().map(Vec::as_slice)).unwrap();
+
+    let opts = |shuffle| ReshardOpts {
+        inputs: vec![input.clone()],
+        output: directory.join("output"),
+        name: "train".to_owned(),
+        shards: 3,
+        shuffle,
+        filters: Vec::new(),
+        progress: false,
+    };
+    let read_shards = |report: &Report| -> Vec<Vec<u8>> {
+        report
+            .shards
+            .iter()
+            .flat_map(|shard| read_records(&shard.path).unwrap())
+            .collect()
+    };
+
+    // Without shuffle, records keep their order within each shard
+    let report = reshard(opts(None)).unwrap();
+    assert_eq!(report.written(), 50);
+    let sizes: Vec<usize> = report.shards.iter().map(|shard| shard.bytes).collect();
+    assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 7);
+    let first = read_records(&report.shards[0].path).unwrap();
+    assert!(first.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+
+    // A seeded shuffle is reproducible, and keeps every record
+    let shuffle = Some(Shuffle { seed: 3, buffer: 8 });
+    let shuffled = read_shards(&reshard(opts(shuffle)).unwrap());
+    assert_eq!(shuffled, read_shards(&reshard(opts(shuffle)).unwrap()));
+    assert_ne!(shuffled, read_shards(&reshard(opts(None)).unwrap()));
+    let mut sorted = shuffled;
+    sorted.sort();
+    assert_eq!(sorted, records);
+
+    // A small input is spread over the shards, instead of being read first
+    let small = directory.join("small.tfrecord");
+    let small_records: Vec<Vec<u8>> = (0..10u8).map(|i| vec![100 + i; 4]).collect();
+    write_records(&small, small_records.iter().map(Vec::as_slice)).unwrap();
+    let mut interleaved = opts(Some(Shuffle { seed: 3, buffer: 1 }));
+    interleaved.inputs.push(small);
+    interleaved.shards = 1;
+    let written = read_shards(&reshard(interleaved).unwrap());
+    let last = written.iter().rposition(|record| record[0] >= 100).unwrap();
+    assert!(last >= 40, "the small input ends at record {}", last);
+
+    let mut overwrite = opts(None);
+    overwrite.output = directory.clone();
+    overwrite.name = "input".to_owned();
+    overwrite.shards = 1;
+    assert!(matches!(
+        reshard(overwrite),
+        Err(ReshardError::Overwrite(_))
+    ));
+    fs::remove_dir_all(&directory).unwrap();
+}
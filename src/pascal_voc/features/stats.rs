@@ -0,0 +1,305 @@
+//! This module implements statistics about a PASCAL-VOC dataset.
+//! - Parse PASCAL-VOC files
+//! - Count images and objects per class
+//! - Summarize the distributions of boxes, objects per image and image resolutions
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use super::prepare::get_xml_paths;
+use crate::math::{self, BBox};
+use crate::pascal_voc::parser::{Annotation, PascalVocError};
+use crate::pascal_voc::split::images_per_class;
+
+/// Configuration options for computing dataset statistics
+#[derive(Debug)]
+pub struct StatsOpts {
+    /// Input directory, where your data set is. Will be searched recursively.
+    pub input: PathBuf,
+    /// How statistics are printed.
+    pub format: StatsFormat,
+}
+
+/// How statistics are printed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Table,
+    Json,
+}
+
+// Takes a directory as a input, will recursively search for PASCAL-VOC files
+// and compute statistics about them
+pub fn stats(opts: StatsOpts) -> Report {
+    let mut report = Report::default();
+
+    let mut examples = Vec::new();
+    get_xml_paths(&opts.input)
+        .iter()
+        .for_each(|path| match Annotation::from_file(path) {
+            Ok(annotation) => examples.push(annotation),
+            Err(e) => report.invalid_annotations.push((path.to_owned(), e)),
+        });
+    report.stats = Stats::new(&examples);
+
+    report
+}
+
+/// Statistics of a set of examples
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub images: usize,
+    pub objects: usize,
+    pub classes: BTreeMap<String, ClassStats>,
+    pub objects_per_image: Summary,
+    /// Box width, in pixels
+    pub box_width: Summary,
+    /// Box height, in pixels
+    pub box_height: Summary,
+    /// Square root of the share of the image covered by the box
+    pub box_relative_size: Summary,
+    /// Box width divided by box height
+    pub box_aspect_ratio: Summary,
+    /// Number of images by resolution, formatted as `<width>x<height>`
+    pub resolutions: BTreeMap<String, usize>,
+    /// Share of objects flagged as truncated
+    pub truncated_ratio: f64,
+    /// Share of objects flagged as difficult
+    pub difficult_ratio: f64,
+}
+
+/// Statistics of a class
+#[derive(Debug, Default, Serialize)]
+pub struct ClassStats {
+    /// Number of images containing at least one object of this class
+    pub images: usize,
+    pub objects: usize,
+    pub truncated: usize,
+    pub difficult: usize,
+}
+
+/// Summary of a distribution of values
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Summary {
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Summary {
+    pub fn new(mut values: Vec<f64>) -> Summary {
+        if values.is_empty() {
+            return Summary::default();
+        }
+
+        values.sort_by(f64::total_cmp);
+        Summary {
+            min: values[0],
+            p25: math::quantile(&values, 0.25),
+            median: math::quantile(&values, 0.5),
+            p75: math::quantile(&values, 0.75),
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+        }
+    }
+}
+
+impl Stats {
+    pub fn new(examples: &[Annotation]) -> Stats {
+        let mut stats = Stats {
+            images: examples.len(),
+            ..Default::default()
+        };
+
+        images_per_class(examples)
+            .into_iter()
+            .for_each(|(name, images)| {
+                stats.classes.entry(name.to_owned()).or_default().images = images;
+            });
+
+        let mut widths = Vec::new();
+        let mut heights = Vec::new();
+        let mut sizes = Vec::new();
+        let mut aspect_ratios = Vec::new();
+        examples.iter().for_each(|example| {
+            let resolution = format!("{}x{}", example.size.width, example.size.height);
+            *stats.resolutions.entry(resolution).or_insert(0) += 1;
+            let image_area = example.size.width as f64 * example.size.height as f64;
+
+            example.objects.iter().for_each(|object| {
+                let class = stats.classes.entry(object.name.clone()).or_default();
+                class.objects += 1;
+                class.truncated += object.truncated as usize;
+                class.difficult += object.difficult as usize;
+
+                let bbox = BBox::from(&object.bndbox);
+                widths.push(bbox.width());
+                heights.push(bbox.height());
+                if image_area > 0f64 {
+                    sizes.push((bbox.area() / image_area).sqrt());
+                }
+                if bbox.height() > 0f64 {
+                    aspect_ratios.push(bbox.aspect_ratio());
+                }
+            });
+        });
+
+        stats.objects = widths.len();
+        let per_image = examples.iter().map(|e| e.objects.len() as f64).collect();
+        stats.objects_per_image = Summary::new(per_image);
+        stats.box_width = Summary::new(widths);
+        stats.box_height = Summary::new(heights);
+        stats.box_relative_size = Summary::new(sizes);
+        stats.box_aspect_ratio = Summary::new(aspect_ratios);
+
+        if stats.objects > 0 {
+            let truncated: usize = stats.classes.values().map(|c| c.truncated).sum();
+            let difficult: usize = stats.classes.values().map(|c| c.difficult).sum();
+            stats.truncated_ratio = truncated as f64 / stats.objects as f64;
+            stats.difficult_ratio = difficult as f64 / stats.objects as f64;
+        }
+
+        stats
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub invalid_annotations: Vec<(PathBuf, PascalVocError)>,
+    pub stats: Stats,
+}
+
+// JSON output, errors are included as text
+#[derive(Serialize)]
+struct JsonReport<'a> {
+    #[serde(flatten)]
+    stats: &'a Stats,
+    invalid_annotations: Vec<JsonError>,
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    path: PathBuf,
+    error: String,
+}
+
+impl Report {
+    pub fn print_report(&self, format: StatsFormat) -> Result<(), serde_json::Error> {
+        match format {
+            StatsFormat::Table => self.print_table(),
+            StatsFormat::Json => {
+                let report = JsonReport {
+                    stats: &self.stats,
+                    invalid_annotations: self
+                        .invalid_annotations
+                        .iter()
+                        .map(|(path, e)| JsonError {
+                            path: path.clone(),
+                            error: e.to_string(),
+                        })
+                        .collect(),
+                };
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn print_table(&self) {
+        let stats = &self.stats;
+        println!(
+            "{} image(s), {} object(s), {} class(es).",
+            stats.images,
+            stats.objects,
+            stats.classes.len()
+        );
+
+        if !stats.classes.is_empty() {
+            let width = stats.classes.keys().map(String::len).max().unwrap_or(0);
+            println!(
+                "{:width$}  {:>8}  {:>8}  {:>9}  {:>9}",
+                "class",
+                "images",
+                "objects",
+                "truncated",
+                "difficult",
+                width = width
+            );
+            stats.classes.iter().for_each(|(name, class)| {
+                println!(
+                    "{:width$}  {:>8}  {:>8}  {:>9}  {:>9}",
+                    name,
+                    class.images,
+                    class.objects,
+                    class.truncated,
+                    class.difficult,
+                    width = width
+                );
+            });
+        }
+
+        println!(
+            "\n{:20}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+            "distribution", "min", "p25", "median", "p75", "max", "mean"
+        );
+        vec![
+            ("objects per image", &stats.objects_per_image),
+            ("box width (px)", &stats.box_width),
+            ("box height (px)", &stats.box_height),
+            ("box relative size", &stats.box_relative_size),
+            ("box aspect ratio", &stats.box_aspect_ratio),
+        ]
+        .into_iter()
+        .for_each(|(name, s)| {
+            println!(
+                "{:20}  {:>8.2}  {:>8.2}  {:>8.2}  {:>8.2}  {:>8.2}  {:>8.2}",
+                name, s.min, s.p25, s.median, s.p75, s.max, s.mean
+            );
+        });
+
+        println!("\nImage resolutions:");
+        stats.resolutions.iter().for_each(|(resolution, count)| {
+            println!("   - {}: {} image(s)", resolution, count);
+        });
+
+        println!(
+            "\n{:.1}% of objects are truncated, {:.1}% are difficult.",
+            stats.truncated_ratio * 100f64,
+            stats.difficult_ratio * 100f64
+        );
+
+        if !self.invalid_annotations.is_empty() {
+            println!(
+                "{} example(s) could not be processed:",
+                self.invalid_annotations.len(),
+            );
+            self.invalid_annotations.iter().for_each(|e| {
+                println!("   - In {:?} - {}", e.0, e.1);
+            })
+        }
+    }
+}
+
+#[test]
+fn test_stats() {
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    let stats = Stats::new(&input);
+
+    assert_eq!(stats.images, 6);
+    assert_eq!(stats.objects, 9);
+    assert_eq!(stats.classes["dog"].images, 4);
+    assert_eq!(stats.classes["hotdog"].images, 4);
+    assert_eq!(stats.classes["hotdog"].objects, 5);
+    assert_eq!(stats.objects_per_image.min, 1.0);
+    assert_eq!(stats.objects_per_image.max, 2.0);
+    assert_eq!(stats.objects_per_image.mean, 1.5);
+    assert_eq!(stats.resolutions.values().sum::<usize>(), 6);
+    assert!(stats.box_width.min <= stats.box_width.median);
+    assert!(stats.box_width.median <= stats.box_width.max);
+}
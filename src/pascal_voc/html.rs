@@ -0,0 +1,306 @@
+//! This module writes the HTML report of `prepare`, a single self-contained file to attach to a
+//! dataset review. Thumbnails are embedded as base64 JPEG images.
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::Error as IoError;
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use rusttype::Font;
+
+use crate::math::BBox;
+use crate::pascal_voc::draw::{class_color, default_font, draw_boxes};
+use crate::pascal_voc::features::prepare::{error_chain, Report, SplitReport};
+use crate::pascal_voc::parser::Annotation;
+
+// Largest side of thumbnails, in pixels
+const THUMBNAIL_SIZE: u32 = 240;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+td.count { text-align: right; }
+.bar { height: 0.8em; display: inline-block; margin-right: 0.4em; }
+.gallery { display: flex; flex-wrap: wrap; gap: 0.5em; }
+figure { margin: 0; font-size: 0.8em; }
+figcaption { max-width: 240px; overflow-wrap: anywhere; }
+.error { color: #a00; }
+";
+
+/// Write the report of `prepare` to an HTML file
+pub fn write_report(path: &Path, report: &Report) -> Result<(), IoError> {
+    fs::write(path, render(report))
+}
+
+// Render the whole page
+fn render(report: &Report) -> String {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str("<title>Dataset report</title>\n");
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    html.push_str("<h1>Dataset report</h1>\n");
+
+    render_summary(&mut html, report);
+    render_histograms(&mut html, report);
+    render_labels(&mut html, report);
+    render_errors(
+        &mut html,
+        "Invalid annotations",
+        report
+            .invalid_annotations
+            .iter()
+            .map(|(path, e)| (path.as_path(), e as &dyn Error)),
+    );
+    render_errors(
+        &mut html,
+        "Dropped examples",
+        report
+            .dropped_examples
+            .iter()
+            .map(|(path, e)| (path.as_path(), e as &dyn Error)),
+    );
+    render_galleries(&mut html, report);
+
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+// Counts and output files
+fn render_summary(html: &mut String, report: &Report) {
+    html.push_str("<h2>Summary</h2>\n<table>\n");
+    let rows = [
+        ("Examples found", report.total_examples()),
+        ("Valid annotations", report.valid_annotations),
+        ("Invalid annotations", report.invalid_annotations.len()),
+        ("Dropped examples", report.dropped_examples.len()),
+        ("Transcoded images", report.transcoded.len()),
+        ("Duplicate clusters", report.duplicates.len()),
+        ("Dropped duplicates", report.dropped_duplicates),
+        ("Excluded difficult objects", report.excluded_difficult),
+        ("Assigned by the input manifest", report.from_manifest),
+    ];
+    rows.iter().for_each(|(name, count)| {
+        let _ = writeln!(
+            html,
+            "<tr><th>{}</th><td class=\"count\">{}</td></tr>",
+            name, count
+        );
+    });
+    html.push_str("</table>\n");
+
+    html.push_str("<table>\n<tr><th>Set</th><th>Examples</th><th>Files</th></tr>\n");
+    report.splits.iter().for_each(|split| {
+        let files: Vec<String> = split
+            .files
+            .iter()
+            .map(|f| escape(&f.to_string_lossy()))
+            .collect();
+        let path = if files.is_empty() {
+            "empty, not written".to_owned()
+        } else {
+            files.join("<br>")
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"count\">{}</td><td>{}</td></tr>",
+            escape(&split.name),
+            split.examples,
+            path
+        );
+    });
+    html.push_str("</table>\n");
+}
+
+// Number of images containing each class, one bar per set
+fn render_histograms(html: &mut String, report: &Report) {
+    let classes: BTreeSet<&String> = report
+        .splits
+        .iter()
+        .flat_map(|s| s.class_counts.keys())
+        .collect();
+    if classes.is_empty() {
+        return;
+    }
+
+    html.push_str("<h2>Images per class</h2>\n");
+    report.splits.iter().for_each(|split| {
+        let max = split.class_counts.values().copied().max().unwrap_or(0).max(1);
+        let _ = writeln!(html, "<h3>{}</h3>\n<table>", escape(&split.name));
+        classes.iter().for_each(|class| {
+            let count = split.class_counts.get(*class).copied().unwrap_or(0);
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td><span class=\"bar\" style=\"width: {}px; background: {}\"></span>{}</td></tr>",
+                escape(class),
+                count * 300 / max,
+                css_color(class),
+                count
+            );
+        });
+        html.push_str("</table>\n");
+    });
+}
+
+// Classes renamed and objects left out by the label rules
+fn render_labels(html: &mut String, report: &Report) {
+    let labels = &report.labels;
+    if labels.renamed.is_empty() && labels.dropped.is_empty() {
+        return;
+    }
+
+    html.push_str("<h2>Label rules</h2>\n<table>\n");
+    html.push_str("<tr><th>Class</th><th>Mapped to</th><th>Objects</th></tr>\n");
+    labels.renamed.iter().for_each(|(from, renamed)| {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td><td class=\"count\">{}</td></tr>",
+            escape(from),
+            escape(&renamed.to),
+            renamed.objects
+        );
+    });
+    labels.dropped.iter().for_each(|(class, count)| {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>left out</td><td class=\"count\">{}</td></tr>",
+            escape(class),
+            count
+        );
+    });
+    html.push_str("</table>\n");
+}
+
+// Errors, with the chain of their causes
+fn render_errors<'a, I>(html: &mut String, title: &str, errors: I)
+where
+    I: Iterator<Item = (&'a Path, &'a dyn Error)>,
+{
+    let mut errors = errors.peekable();
+    if errors.peek().is_none() {
+        return;
+    }
+
+    let _ = writeln!(html, "<h2>{}</h2>\n<ul>", title);
+    errors.for_each(|(path, e)| {
+        let _ = writeln!(
+            html,
+            "<li><code>{}</code> <span class=\"error\">{}</span></li>",
+            escape(&path.to_string_lossy()),
+            escape(&error_chain(e).join(": "))
+        );
+    });
+    html.push_str("</ul>\n");
+}
+
+// Thumbnails of the sampled examples of each set
+fn render_galleries(html: &mut String, report: &Report) {
+    let font = match default_font() {
+        Some(font) => font,
+        None => return,
+    };
+    if report.splits.iter().all(|s| s.samples.is_empty()) {
+        return;
+    }
+
+    html.push_str("<h2>Samples</h2>\n");
+    report
+        .splits
+        .iter()
+        .filter(|split| !split.samples.is_empty())
+        .for_each(|split| render_gallery(html, split, &font));
+}
+
+fn render_gallery(html: &mut String, split: &SplitReport, font: &Font) {
+    let _ = writeln!(
+        html,
+        "<h3>{}</h3>\n<div class=\"gallery\">",
+        escape(&split.name)
+    );
+    split.samples.iter().for_each(|path| {
+        let caption = escape(&path.to_string_lossy());
+        let thumbnail = Annotation::from_file(path)
+            .map_err(|e| error_chain(&e).join(": "))
+            .and_then(|annotation| {
+                thumbnail(&annotation, font).map_err(|e| error_chain(&e).join(": "))
+            });
+        let _ = match thumbnail {
+            Ok(data) => writeln!(
+                html,
+                "<figure><img src=\"data:image/jpeg;base64,{}\"><figcaption>{}</figcaption></figure>",
+                data, caption
+            ),
+            Err(e) => writeln!(
+                html,
+                "<figure><figcaption>{}<br><span class=\"error\">{}</span></figcaption></figure>",
+                caption,
+                escape(&e)
+            ),
+        };
+    });
+    html.push_str("</div>\n");
+}
+
+// Resized image of an example with its boxes, as base64 JPEG
+fn thumbnail(annotation: &Annotation, font: &Font) -> Result<String, image::ImageError> {
+    let image = image::open(&annotation.system_path)?;
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let mut image = image
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgb8();
+    let sx = image.width() as f64 / width;
+    let sy = image.height() as f64 / height;
+
+    let boxes: Vec<(BBox, String)> = annotation
+        .objects
+        .iter()
+        .map(|o| (BBox::from(&o.bndbox).scale(sx, sy), o.name.clone()))
+        .collect();
+    draw_boxes(&mut image, &boxes, font);
+
+    let mut bytes = Vec::new();
+    DynamicImage::ImageRgb8(image).write_to(&mut bytes, ImageOutputFormat::Jpeg(80))?;
+    Ok(base64::encode(&bytes))
+}
+
+fn css_color(class: &str) -> String {
+    let color = class_color(class);
+    format!("rgb({}, {}, {})", color[0], color[1], color[2])
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[test]
+fn test_render() {
+    use std::path::PathBuf;
+
+    let mut report = Report {
+        valid_annotations: 1,
+        ..Default::default()
+    };
+    report.invalid_annotations.push((
+        PathBuf::from("<broken>.xml"),
+        Annotation::from_file(Path::new("./dataset/missing.xml")).unwrap_err(),
+    ));
+    report.splits.push(SplitReport {
+        name: "train".to_owned(),
+        examples: 1,
+        class_counts: vec![("dog".to_owned(), 1)].into_iter().collect(),
+        samples: vec![PathBuf::from("./dataset/1.xml")],
+        ..Default::default()
+    });
+
+    let html = render(&report);
+    assert!(html.contains("&lt;broken&gt;.xml"));
+    assert!(html.contains("Io error while attempting to read the example: "));
+    assert!(html.contains("<h3>train</h3>"));
+    assert!(html.contains("data:image/jpeg;base64,"));
+}
@@ -1,17 +1,67 @@
-//! This module implements helpers for generating label maps.
+//! This module implements helpers for generating and reading label maps.
 use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
 use std::io::{BufWriter, Error as IoError, Write};
 use std::path::Path;
 
+use serde::Deserialize;
 use thiserror::Error;
 
-use crate::tensorflow_protos::string_int_label_map::{StringIntLabelMap, StringIntLabelMapItem};
+use crate::pascal_voc::pbtxt::{Message, PbtxtError, Value};
+use crate::tensorflow_protos::string_int_label_map::{
+    StringIntLabelMap, StringIntLabelMapItem, StringIntLabelMapItem_KeypointMap,
+};
 
 #[derive(Debug, Clone, Default)]
 pub struct LabelMap {
     index: i64,
     map: HashMap<String, i64>,
+    /// Label of each ID, the reverse of `map`
+    labels: HashMap<i64, String>,
+    /// Optional fields of each label
+    info: HashMap<String, ClassInfo>,
+}
+
+/// Optional fields of a label map item, besides its name and ID
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClassInfo {
+    /// Human readable name, shown by evaluation tools and dashboards
+    pub display_name: Option<String>,
+    /// Keypoints of the class, for keypoint detection
+    pub keypoints: Vec<Keypoint>,
+    /// IDs of the parent classes in a hierarchy
+    pub ancestor_ids: Vec<i64>,
+    /// IDs of the child classes in a hierarchy
+    pub descendant_ids: Vec<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keypoint {
+    pub id: i64,
+    pub label: String,
+}
+
+/// Class definitions, read from a TOML file with a table per class name:
+/// ```toml
+/// [cls_07_hd]
+/// display_name = "Hot dog"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct ClassDefinitions {
+    pub classes: HashMap<String, ClassInfo>,
+}
+
+impl ClassDefinitions {
+    /// Load class definitions from a TOML file
+    pub fn from_file(path: &Path) -> Result<ClassDefinitions, LabelMapError> {
+        let content = fs::read_to_string(path)?;
+        let definitions = toml::from_str(&content)?;
+
+        Ok(definitions)
+    }
 }
 
 impl LabelMap {
@@ -23,26 +73,145 @@ impl LabelMap {
         }
     }
 
+    /// Read a label map written in protobuf text format, such as the ones of the object detection API
+    pub fn from_file(path: &Path) -> Result<LabelMap, LabelMapError> {
+        LabelMap::from_pbtxt(&fs::read_to_string(path)?)
+    }
+
+    /// Parse a label map written in protobuf text format. Fields other than the name, ID and the ones
+    /// of `ClassInfo` are ignored.
+    pub fn from_pbtxt(input: &str) -> Result<LabelMap, LabelMapError> {
+        let mut label_map = LabelMap::new();
+        for (index, item) in Message::parse(input)?.get("item").enumerate() {
+            let item = match item {
+                Value::Message(item) => item,
+                Value::Scalar(_) => return Err(LabelMapError::Item(index)),
+            };
+            let name = item.scalar("name").ok_or(LabelMapError::Item(index))?;
+            let id = item
+                .scalar("id")
+                .and_then(|id| id.parse().ok())
+                .ok_or(LabelMapError::Item(index))?;
+            label_map.insert(name, id)?;
+
+            let info = ClassInfo {
+                display_name: item.scalar("display_name").map(|n| n.to_owned()),
+                keypoints: item
+                    .get("keypoints")
+                    .filter_map(|keypoint| match keypoint {
+                        Value::Message(keypoint) => Some(Keypoint {
+                            id: keypoint.scalar("id")?.parse().ok()?,
+                            label: keypoint.scalar("label").unwrap_or_default().to_owned(),
+                        }),
+                        Value::Scalar(_) => None,
+                    })
+                    .collect(),
+                ancestor_ids: scalar_ids(item, "ancestor_ids"),
+                descendant_ids: scalar_ids(item, "descendant_ids"),
+            };
+            label_map.set_info(name, info);
+        }
+
+        Ok(label_map)
+    }
+
     /// Add a label to the collection. It's safe to call this function repeatedly with the same label.
     /// Always returns the correct ID for a given label.
     pub fn add(&mut self, label: &str) -> i64 {
-        let current = self.index;
-        if self.map.get(label).is_none() {
-            self.map.insert(label.to_owned(), current);
-            self.index += 1;
+        if let Some(id) = self.get(label) {
+            return id;
         }
+        let current = self.index;
+        self.map.insert(label.to_owned(), current);
+        self.labels.insert(current, label.to_owned());
+        self.index += 1;
         current
     }
 
+    /// Add a label with a given ID, labels added afterwards get higher IDs.
+    /// Fails if the label or the ID is already in the map.
+    pub fn insert(&mut self, label: &str, id: i64) -> Result<(), LabelMapError> {
+        if self.map.contains_key(label) {
+            return Err(LabelMapError::DuplicateLabel(label.to_owned()));
+        }
+        if let Some(other) = self.label(id) {
+            return Err(LabelMapError::DuplicateId(id, other.to_owned()));
+        }
+        self.map.insert(label.to_owned(), id);
+        self.labels.insert(id, label.to_owned());
+        self.index = self.index.max(id + 1);
+
+        Ok(())
+    }
+
     /// Get the ID for a label
     pub fn get(&self, label: &str) -> Option<i64> {
         self.map.get(label).copied()
     }
 
+    /// Get the label of an ID
+    pub fn label(&self, id: i64) -> Option<&str> {
+        self.labels.get(&id).map(String::as_str)
+    }
+
+    /// Optional fields of a label
+    pub fn info(&self, label: &str) -> Option<&ClassInfo> {
+        self.info.get(label)
+    }
+
+    /// Set the optional fields of a label, nothing is set if the label is not in the map
+    pub fn set_info(&mut self, label: &str, info: ClassInfo) {
+        if self.map.contains_key(label) && info != ClassInfo::default() {
+            self.info.insert(label.to_owned(), info);
+        }
+    }
+
+    /// Set the optional fields of the labels that have a definition.
+    /// Returns the defined classes that are not in the map, ordered by name.
+    pub fn define(&mut self, definitions: &ClassDefinitions) -> Vec<String> {
+        let mut unknown: Vec<String> = definitions
+            .classes
+            .iter()
+            .filter_map(|(label, info)| {
+                if self.map.contains_key(label) {
+                    self.set_info(label, info.clone());
+                    None
+                } else {
+                    Some(label.clone())
+                }
+            })
+            .collect();
+        unknown.sort();
+        unknown
+    }
+
+    /// Labels and their IDs, ordered by ID
+    pub fn items(&self) -> Vec<(&str, i64)> {
+        let mut items: Vec<(&str, i64)> = self
+            .map
+            .iter()
+            .map(|(label, &id)| (label.as_str(), id))
+            .collect();
+        items.sort_by_key(|&(_, id)| id);
+        items
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Label map in protobuf text format
+    pub fn to_pbtxt(&self) -> String {
+        format!("{:#?}", StringIntLabelMap::from(self.clone()))
+    }
+
     /// Write examples added to the builder to a tfrecord file
     pub fn write_to_file(self, path: &Path) -> Result<(), LabelMapError> {
-        let protobuf = StringIntLabelMap::from(self);
-        let pbtxt = format!("{:#?}", protobuf);
+        let pbtxt = self.to_pbtxt();
         let file = OpenOptions::new()
             .write(true)
             .create(true)
@@ -59,12 +228,32 @@ impl LabelMap {
 impl From<LabelMap> for StringIntLabelMap {
     fn from(input: LabelMap) -> StringIntLabelMap {
         let content = input
-            .map
+            .items()
             .into_iter()
             .map(|(label, id)| {
                 let mut item = StringIntLabelMapItem::new();
-                item.set_name(label);
+                item.set_name(label.to_owned());
                 item.set_id(id as i32);
+                if let Some(info) = input.info(label) {
+                    if let Some(display_name) = &info.display_name {
+                        item.set_display_name(display_name.clone());
+                    }
+                    item.set_keypoints(
+                        info.keypoints
+                            .iter()
+                            .map(|k| {
+                                let mut keypoint = StringIntLabelMapItem_KeypointMap::new();
+                                keypoint.set_id(k.id as i32);
+                                keypoint.set_label(k.label.clone());
+                                keypoint
+                            })
+                            .collect(),
+                    );
+                    item.set_ancestor_ids(info.ancestor_ids.iter().map(|&i| i as i32).collect());
+                    item.set_descendant_ids(
+                        info.descendant_ids.iter().map(|&i| i as i32).collect(),
+                    );
+                }
                 item
             })
             .collect();
@@ -76,9 +265,84 @@ impl From<LabelMap> for StringIntLabelMap {
     }
 }
 
+// Integers of a repeated field, values that are not integers are skipped
+fn scalar_ids(item: &Message, field: &str) -> Vec<i64> {
+    item.get(field)
+        .filter_map(|value| match value {
+            Value::Scalar(id) => id.parse().ok(),
+            Value::Message(_) => None,
+        })
+        .collect()
+}
+
 /// Error types you might encounter while working with label maps
 #[derive(Debug, Error)]
 pub enum LabelMapError {
-    #[error("Io error while attempting to write label map")]
+    #[error("Io error while attempting to read or write label map")]
     Io(#[from] IoError),
+
+    #[error("Invalid label map")]
+    Syntax(#[from] PbtxtError),
+
+    #[error("Item {0} of the label map needs a name and an integer id")]
+    Item(usize),
+
+    #[error("Label {0:?} appears twice in the label map")]
+    DuplicateLabel(String),
+
+    #[error("ID {0} is already used by {1:?} in the label map")]
+    DuplicateId(i64, String),
+
+    #[error("Failed to deserialize the class definitions")]
+    Definitions(#[from] toml::de::Error),
+}
+
+#[test]
+fn test_read_label_map() {
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+    assert_eq!(label_map.add("dog"), 1);
+
+    let pbtxt = format!("{:#?}", StringIntLabelMap::from(label_map));
+    let read = LabelMap::from_pbtxt(&pbtxt).unwrap();
+    assert_eq!(read.items(), vec![("dog", 1), ("hotdog", 2)]);
+
+    let mut read = LabelMap::from_pbtxt("item { name: 'cat' id: 5 }").unwrap();
+    assert_eq!(read.add("dog"), 6);
+    assert_eq!(read.label(5), Some("cat"));
+    assert_eq!(read.label(6), Some("dog"));
+    assert_eq!(read.label(1), None);
+    assert!(matches!(
+        read.insert("bird", 5),
+        Err(LabelMapError::DuplicateId(5, _))
+    ));
+    assert!(matches!(
+        LabelMap::from_pbtxt("item { name: 'cat' }"),
+        Err(LabelMapError::Item(0))
+    ));
+}
+
+#[test]
+fn test_class_info() {
+    let mut label_map = LabelMap::from_pbtxt("item { name: 'cls_07_hd' id: 3 }").unwrap();
+    let definitions: ClassDefinitions = toml::from_str(
+        r#"
+        [cls_07_hd]
+        display_name = "Hot dog"
+        ancestor_ids = [1]
+        keypoints = [{ id = 0, label = "tip" }]
+
+        [unused]
+        display_name = "Unused"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(label_map.define(&definitions), vec!["unused".to_owned()]);
+
+    let pbtxt = format!("{:#?}", StringIntLabelMap::from(label_map.clone()));
+    let read = LabelMap::from_pbtxt(&pbtxt).unwrap();
+    let info = read.info("cls_07_hd").unwrap();
+    assert_eq!(info.display_name.as_deref(), Some("Hot dog"));
+    assert_eq!(info, label_map.info("cls_07_hd").unwrap());
 }
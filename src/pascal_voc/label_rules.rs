@@ -0,0 +1,197 @@
+//! This module maps the class names of annotations before the label map is generated.
+//! Annotators don't always agree on casing and spelling, rules rename and merge the variants of
+//! a class, and leave unwanted classes out.
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Error as IoError;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::pascal_voc::parser::Annotation;
+
+/// Rules applied to each class name, in this order:
+/// - `rename` and `merge`, matched against the name as written, then lowercased with `lowercase`
+/// - `lowercase`, for names that no rule renamed
+/// - `ignore` and `keep`, matched against the new name
+#[derive(Debug, Clone, Default)]
+pub struct LabelRules {
+    lowercase: bool,
+    rename: HashMap<String, String>,
+    ignore: Vec<String>,
+    keep: Option<Vec<String>>,
+}
+
+// Content of a rules file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RulesFile {
+    lowercase: bool,
+    /// New name of each class
+    rename: HashMap<String, String>,
+    /// Names merged into each class
+    merge: HashMap<String, Vec<String>>,
+    /// Classes left out
+    ignore: Vec<String>,
+    /// Only these classes are kept, when set
+    keep: Option<Vec<String>>,
+}
+
+/// What the rules did to the objects of a dataset
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LabelMapping {
+    /// Renamed classes, by name as written in the annotations
+    pub renamed: BTreeMap<String, RenamedClass>,
+    /// Number of objects left out, by class after renaming
+    pub dropped: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RenamedClass {
+    pub to: String,
+    /// Number of objects renamed
+    pub objects: usize,
+}
+
+impl LabelRules {
+    /// Load rules from a TOML file
+    pub fn from_file(path: &Path) -> Result<LabelRules, LabelRulesError> {
+        let content = fs::read_to_string(path)?;
+        LabelRules::from_toml(&content)
+    }
+
+    fn from_toml(content: &str) -> Result<LabelRules, LabelRulesError> {
+        let file: RulesFile = toml::from_str(content)?;
+
+        // Merges are renames of each synonym, a name can only be mapped to a single class
+        let mut rename = file.rename;
+        for (class, synonyms) in file.merge {
+            for synonym in synonyms {
+                match rename.get(&synonym) {
+                    Some(other) if *other != class => {
+                        return Err(LabelRulesError::Conflict(synonym, other.clone(), class))
+                    }
+                    _ => rename.insert(synonym, class.clone()),
+                };
+            }
+        }
+
+        Ok(LabelRules {
+            lowercase: file.lowercase,
+            rename,
+            ignore: file.ignore,
+            keep: file.keep,
+        })
+    }
+
+    /// New name of a class, None if the class is left out
+    pub fn map(&self, name: &str) -> Option<String> {
+        let renamed = self.rename(name);
+        if self.is_kept(&renamed) {
+            Some(renamed)
+        } else {
+            None
+        }
+    }
+
+    // Name after the `rename`, `merge` and `lowercase` rules
+    fn rename(&self, name: &str) -> String {
+        let lowercased = if self.lowercase {
+            name.to_lowercase()
+        } else {
+            name.to_owned()
+        };
+        self.rename
+            .get(name)
+            .or_else(|| self.rename.get(&lowercased))
+            .cloned()
+            .unwrap_or(lowercased)
+    }
+
+    // Whether a renamed class passes the `ignore` and `keep` rules
+    fn is_kept(&self, name: &str) -> bool {
+        let ignored = self.ignore.iter().any(|n| n == name);
+        let kept = match &self.keep {
+            Some(keep) => keep.iter().any(|n| n == name),
+            None => true,
+        };
+        !ignored && kept
+    }
+
+    /// Rename the objects of each example and remove the ones that are left out.
+    /// Examples without objects left are kept, as background images.
+    pub fn apply(&self, examples: &mut [Annotation]) -> LabelMapping {
+        let mut mapping = LabelMapping::default();
+        examples.iter_mut().for_each(|example| {
+            let objects = std::mem::take(&mut example.objects);
+            example.objects = objects
+                .into_iter()
+                .filter_map(|mut object| {
+                    let name = self.rename(&object.name);
+                    if !self.is_kept(&name) {
+                        *mapping.dropped.entry(name).or_insert(0) += 1;
+                        return None;
+                    }
+                    if name != object.name {
+                        mapping
+                            .renamed
+                            .entry(object.name.clone())
+                            .or_insert_with(|| RenamedClass {
+                                to: name.clone(),
+                                objects: 0,
+                            })
+                            .objects += 1;
+                        object.name = name;
+                    }
+                    Some(object)
+                })
+                .collect();
+        });
+        mapping
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LabelRulesError {
+    #[error("Io error while attempting to read the label rules")]
+    Io(#[from] IoError),
+
+    #[error("Failed to deserialize the label rules")]
+    Deserialize(#[from] toml::de::Error),
+
+    #[error("{0:?} is mapped to both {1:?} and {2:?}")]
+    Conflict(String, String, String),
+}
+
+#[test]
+fn test_label_rules() {
+    let rules = LabelRules::from_toml(
+        r#"
+        lowercase = true
+        ignore = ["person"]
+
+        [rename]
+        "Hot Dog" = "hotdog"
+
+        [merge]
+        dog = ["puppy", "Doggo"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(rules.map("Dog").as_deref(), Some("dog"));
+    assert_eq!(rules.map("PUPPY").as_deref(), Some("dog"));
+    assert_eq!(rules.map("Doggo").as_deref(), Some("dog"));
+    assert_eq!(rules.map("Hot Dog").as_deref(), Some("hotdog"));
+    assert_eq!(rules.map("Person"), None);
+
+    let rules = LabelRules::from_toml("keep = [\"hotdog\"]").unwrap();
+    let mut examples = vec![Annotation::from_file(Path::new("./dataset/1.xml")).unwrap()];
+    let mapping = rules.apply(&mut examples);
+    assert!(examples[0].objects.iter().all(|o| o.name == "hotdog"));
+    assert_eq!(mapping.dropped.get("dog"), Some(&1));
+    assert!(mapping.renamed.is_empty());
+
+    let conflict = LabelRules::from_toml("[rename]\npuppy = \"cat\"\n[merge]\ndog = [\"puppy\"]");
+    assert!(matches!(conflict, Err(LabelRulesError::Conflict(..))));
+}
@@ -0,0 +1,164 @@
+//! This module implements split manifests: the list of examples assigned to each set.
+//! Manifests follow the PASCAL-VOC ImageSets layout, one `<set>.txt` file per set and one
+//! example ID per line. The ID of an example is the path of its PASCAL-VOC file, relative to
+//! the input directory and without extension, for instance `2007/000032`.
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Error as IoError, Write};
+use std::path::{Component, Path, PathBuf};
+
+use thiserror::Error;
+
+use super::parser::Annotation;
+
+/// Example IDs, by set name
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    sets: BTreeMap<String, Vec<String>>,
+}
+
+impl Manifest {
+    /// Build a manifest from split examples, `root` is the input directory
+    pub fn new(root: &Path, names: &[&str], sets: &[Vec<Annotation>]) -> Manifest {
+        let sets = names
+            .iter()
+            .zip(sets)
+            .map(|(name, set)| {
+                let ids = set.iter().map(|a| example_id(root, a)).collect();
+                (name.to_string(), ids)
+            })
+            .collect();
+
+        Manifest { sets }
+    }
+
+    /// Read all `<set>.txt` files from a directory
+    pub fn from_dir(directory: &Path) -> Result<Manifest, ManifestError> {
+        let mut sets = BTreeMap::new();
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let name = match (path.extension(), path.file_stem()) {
+                (Some(ext), Some(name)) if ext == "txt" => name.to_string_lossy().into_owned(),
+                _ => continue,
+            };
+
+            let ids = fs::read_to_string(&path)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_owned)
+                .collect();
+            sets.insert(name, ids);
+        }
+
+        Ok(Manifest { sets })
+    }
+
+    /// Write one `<set>.txt` file per set to a directory, created if needed.
+    /// Files of sets that are not part of the manifest are removed.
+    pub fn write_to_dir(&self, directory: &Path) -> Result<(), ManifestError> {
+        fs::create_dir_all(directory)?;
+        for entry in fs::read_dir(directory)? {
+            let path = entry?.path();
+            let stale = match (path.extension(), path.file_stem()) {
+                (Some(ext), Some(name)) => {
+                    ext == "txt" && !self.sets.contains_key(&*name.to_string_lossy())
+                }
+                _ => false,
+            };
+            if stale {
+                fs::remove_file(path)?;
+            }
+        }
+
+        for (name, ids) in &self.sets {
+            let file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(directory.join(format!("{}.txt", name)))?;
+
+            let mut buffered_writer = BufWriter::new(file);
+            for id in ids {
+                writeln!(buffered_writer, "{}", id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Names of the sets in the manifest
+    pub fn set_names(&self) -> impl Iterator<Item = &str> {
+        self.sets.keys().map(String::as_str)
+    }
+
+    /// Map example IDs to the name of their set.
+    /// Fails if an example is listed in more than one set.
+    pub fn assignments(&self) -> Result<HashMap<&str, &str>, ManifestError> {
+        let mut assignments = HashMap::new();
+        for (name, ids) in &self.sets {
+            for id in ids {
+                if let Some(other) = assignments.insert(id.as_str(), name.as_str()) {
+                    return Err(ManifestError::Conflict(
+                        id.clone(),
+                        other.to_owned(),
+                        name.clone(),
+                    ));
+                }
+            }
+        }
+
+        Ok(assignments)
+    }
+}
+
+/// ID of an example: path of its PASCAL-VOC file relative to `root`, without extension
+pub fn example_id(root: &Path, annotation: &Annotation) -> String {
+    let path = annotation
+        .annotation_path
+        .strip_prefix(root)
+        .unwrap_or(&annotation.annotation_path)
+        .with_extension("");
+
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Default location of the manifest, under the output directory
+pub fn manifest_dir(output: &Path) -> PathBuf {
+    output.join("splits")
+}
+
+/// Error types you might encounter while working with split manifests
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Io error while attempting to read or write the split manifest")]
+    Io(#[from] IoError),
+
+    #[error("Example {0:?} is listed in two sets: {1} and {2}")]
+    Conflict(String, String, String),
+
+    #[error("{0:?} and {1:?} are duplicates or in the same group, but the manifest lists them in {2} and {3}")]
+    Separated(String, String, String, String),
+
+    #[error("Set {0:?} from the manifest is not part of the requested split")]
+    UnknownSet(String),
+}
+
+#[test]
+fn test_example_id() {
+    let mut annotation = Annotation::from_file(&PathBuf::from("./dataset/1.xml")).unwrap();
+    assert_eq!(example_id(Path::new("./dataset"), &annotation), "1");
+    assert_eq!(example_id(Path::new("."), &annotation), "dataset/1");
+
+    annotation.annotation_path = PathBuf::from("/data/voc/2007/000032.xml");
+    assert_eq!(
+        example_id(Path::new("/data/voc"), &annotation),
+        "2007/000032"
+    );
+}
@@ -1,8 +1,40 @@
 //! PASCAL-VOC features and helpers.
+mod draw;
+mod duplicates;
+mod html;
 mod label_map;
+mod label_rules;
+mod manifest;
 mod parser;
+mod pbtxt;
+mod predicate;
+mod schema;
+mod split;
 mod tfrecord;
 
 mod features;
 
-pub use features::prepare::{prepare, PrepareOpts, Report as PrepareReport};
+pub use duplicates::{DuplicateHandling, DuplicateOpts};
+pub use features::anchors::{anchors, AnchorsOpts, Report as AnchorsReport};
+pub use features::check_labels::{
+    check_labels, CheckLabelsError, CheckLabelsOpts, Report as CheckLabelsReport,
+};
+pub use features::convert::{
+    convert, read_label_map, write_label_map, ConvertError, ConvertOpts, LabelMapFormat,
+    Report as ConvertReport,
+};
+pub use features::dedupe::{dedupe, DedupeOpts, Report as DedupeReport};
+pub use features::label_maps::{merge_label_maps, MergeOpts, Report as MergeReport};
+pub use features::prepare::{prepare, FailOn, PrepareOpts, Report as PrepareReport, ReportFormat};
+pub use features::preview::{
+    preview, PreviewError, PreviewOpts, PreviewSource, Report as PreviewReport,
+};
+pub use features::reshard::{reshard, Report as ReshardReport, ReshardError, ReshardOpts, Shuffle};
+pub use features::stats::{stats, Report as StatsReport, StatsFormat, StatsOpts};
+pub use label_map::{ClassDefinitions, ClassInfo, Keypoint, LabelMap, LabelMapError};
+pub use label_rules::{LabelMapping, LabelRules, LabelRulesError, RenamedClass};
+pub use parser::{Annotation, BndBox, Object, PascalVocError, Size, Source};
+pub use predicate::{Predicate, PredicateError};
+pub use schema::{Schema, SchemaError};
+pub use split::{AnnotationField, GroupBy, SplitMode, SplitOpts, SplitSpec};
+pub use tfrecord::ImageEncoding;
@@ -7,6 +7,8 @@ use quick_xml::DeError as DeserializeError;
 use serde::Deserialize;
 use thiserror::Error;
 
+use crate::math::BBox;
+
 /// A PASCAL-VOC XML annotation, this is the main object type
 #[derive(Debug, Deserialize, Clone)]
 pub struct Annotation {
@@ -24,6 +26,9 @@ pub struct Annotation {
     /// we can build the correct path.
     #[serde(skip)]
     pub system_path: PathBuf,
+    /// Generated field. Path of the PASCAL-VOC file this annotation was read from.
+    #[serde(skip)]
+    pub annotation_path: PathBuf,
     /// Source database (might be missing/irrelevant).
     pub source: Source,
     /// Dimensions of the image.
@@ -42,6 +47,7 @@ impl Annotation {
         let mut system_path = path.to_owned();
         system_path.set_file_name(&example.filename);
         example.system_path = system_path;
+        example.annotation_path = path.to_owned();
 
         Ok(example)
     }
@@ -82,6 +88,17 @@ pub struct BndBox {
     pub ymax: u32,
 }
 
+impl From<&BndBox> for BBox {
+    fn from(bndbox: &BndBox) -> Self {
+        BBox::from_xyxy(
+            bndbox.xmin as f64,
+            bndbox.ymin as f64,
+            bndbox.xmax as f64,
+            bndbox.ymax as f64,
+        )
+    }
+}
+
 /// Error types you might encounter while working with PASCAL VOC files
 #[derive(Debug, Error)]
 pub enum PascalVocError {
@@ -0,0 +1,250 @@
+//! This module parses protobuf text format, as written in `.pbtxt` label maps.
+//! Messages are parsed without their schema, into a tree of fields.
+use std::iter::Peekable;
+use std::str::Chars;
+
+use thiserror::Error;
+
+/// Value of a field, scalars are kept as written, strings are unquoted
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Message(Message),
+}
+
+/// Fields of a message, in order. Repeated fields appear once per value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Message {
+    pub fields: Vec<(String, Value)>,
+}
+
+impl Message {
+    /// Parse a message written in text format
+    pub fn parse(input: &str) -> Result<Message, PbtxtError> {
+        let mut parser = Parser {
+            chars: input.chars().peekable(),
+            line: 1,
+        };
+        let message = parser.message()?;
+        match parser.next_token()? {
+            None => Ok(message),
+            Some(token) => Err(parser.error(format!("unexpected {:?}", token))),
+        }
+    }
+
+    /// Values of a field, empty if the field is missing
+    pub fn get<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Last scalar value of a field, as protobuf keeps the last value of non-repeated fields
+    pub fn scalar(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .rev()
+            .find_map(|(field, value)| match value {
+                Value::Scalar(scalar) if field == name => Some(scalar.as_str()),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // Field names, numbers, enum values and booleans
+    Word(String),
+    Str(String),
+    Symbol(char),
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Fields until the end of the input or a closing bracket, which is left to the caller
+    fn message(&mut self) -> Result<Message, PbtxtError> {
+        let mut message = Message::default();
+        loop {
+            self.skip_separators();
+            match self.chars.peek() {
+                None | Some('}') | Some('>') => return Ok(message),
+                _ => {}
+            }
+
+            let name = match self.next_token()? {
+                Some(Token::Word(name)) => name,
+                token => return Err(self.error(format!("expected a field name, got {:?}", token))),
+            };
+            self.skip_whitespace();
+            if self.chars.peek() == Some(&':') {
+                self.chars.next();
+            }
+
+            match self.next_token()? {
+                Some(Token::Symbol('[')) => loop {
+                    match self.next_token()? {
+                        Some(Token::Symbol(']')) => break,
+                        Some(Token::Symbol(',')) => continue,
+                        token => {
+                            let value = self.value(token)?;
+                            message.fields.push((name.clone(), value));
+                        }
+                    }
+                },
+                token => {
+                    let value = self.value(token)?;
+                    message.fields.push((name, value));
+                }
+            }
+        }
+    }
+
+    // Value starting with the given token
+    fn value(&mut self, token: Option<Token>) -> Result<Value, PbtxtError> {
+        match token {
+            Some(Token::Word(word)) => Ok(Value::Scalar(word)),
+            Some(Token::Str(mut string)) => {
+                // Adjacent strings are concatenated
+                self.skip_whitespace();
+                while let Some('"') | Some('\'') = self.chars.peek() {
+                    if let Some(Token::Str(next)) = self.next_token()? {
+                        string.push_str(&next);
+                    }
+                    self.skip_whitespace();
+                }
+                Ok(Value::Scalar(string))
+            }
+            Some(Token::Symbol(open)) if open == '{' || open == '<' => {
+                let message = self.message()?;
+                let close = if open == '{' { '}' } else { '>' };
+                match self.next_token()? {
+                    Some(Token::Symbol(c)) if c == close => Ok(Value::Message(message)),
+                    token => Err(self.error(format!("expected {:?}, got {:?}", close, token))),
+                }
+            }
+            token => Err(self.error(format!("expected a value, got {:?}", token))),
+        }
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, PbtxtError> {
+        self.skip_whitespace();
+        let c = match self.chars.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = match c {
+            '"' | '\'' => Token::Str(self.string(c)?),
+            c if c.is_alphanumeric() || "_-+.".contains(c) => {
+                let mut word = c.to_string();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_alphanumeric() || "_-+.".contains(c)) {
+                        break;
+                    }
+                    word.push(c);
+                    self.chars.next();
+                }
+                Token::Word(word)
+            }
+            c => Token::Symbol(c),
+        };
+        Ok(Some(token))
+    }
+
+    // Rest of a quoted string, with its escape sequences
+    fn string(&mut self, quote: char) -> Result<String, PbtxtError> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return Err(self.error("unterminated string".to_owned())),
+                Some(c) if c == quote => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some(c) => string.push(c),
+                    None => return Err(self.error("unterminated string".to_owned())),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    // Whitespace and comments
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.chars.peek() {
+            match c {
+                '\n' => self.line += 1,
+                '#' => {
+                    while let Some(&c) = self.chars.peek() {
+                        if c == '\n' {
+                            break;
+                        }
+                        self.chars.next();
+                    }
+                    continue;
+                }
+                c if c.is_whitespace() => {}
+                _ => return,
+            }
+            self.chars.next();
+        }
+    }
+
+    // Fields can be separated by commas or semicolons
+    fn skip_separators(&mut self) {
+        self.skip_whitespace();
+        while let Some(',') | Some(';') = self.chars.peek() {
+            self.chars.next();
+            self.skip_whitespace();
+        }
+    }
+
+    fn error(&self, message: String) -> PbtxtError {
+        PbtxtError {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Syntax error on line {line}: {message}")]
+pub struct PbtxtError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[test]
+fn test_parse() {
+    let message = Message::parse(
+        r#"
+        # Comment
+        item {
+          name: "/m/01g317"
+          id: 1
+          display_name: 'person'
+        }
+        item: < name: "dog" id: 2, ancestor_ids: [1, 3] >
+        "#,
+    )
+    .unwrap();
+
+    let items: Vec<&Value> = message.get("item").collect();
+    assert_eq!(items.len(), 2);
+    match items[1] {
+        Value::Message(item) => {
+            assert_eq!(item.scalar("name"), Some("dog"));
+            assert_eq!(item.get("ancestor_ids").count(), 2);
+        }
+        _ => panic!("item should be a message"),
+    }
+
+    let error = Message::parse("item {\n name: \"dog\"\n").unwrap_err();
+    assert_eq!(error.line, 3);
+}
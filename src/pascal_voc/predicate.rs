@@ -0,0 +1,237 @@
+//! This module filters serialized examples on the values of their features.
+//! A predicate is written `<key> <operator> <value>`, for instance:
+//! - `image/object/class/text contains dog`, any value of the feature is `dog`
+//! - `image/width >= 640`, compares the first value of the feature
+//! - `count(image/object/bbox/xmin) > 0`, compares the number of values of the feature
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::pascal_voc::tfrecord::TfRecordError;
+use crate::tensorflow_protos::example::Example;
+use crate::tensorflow_protos::feature::Feature;
+
+/// A condition on the features of an example
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    /// Any value of the feature is equal to the value, or none with `!contains`
+    Contains {
+        key: String,
+        value: String,
+        negated: bool,
+    },
+    /// The first value of the feature compared to the value
+    Compare {
+        key: String,
+        operator: Operator,
+        value: String,
+    },
+    /// The number of values of the feature compared to a count
+    Count {
+        key: String,
+        operator: Operator,
+        count: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Operator {
+    fn parse(operator: &str) -> Option<Operator> {
+        match operator {
+            "==" | "=" => Some(Operator::Eq),
+            "!=" => Some(Operator::Ne),
+            "<" => Some(Operator::Lt),
+            "<=" => Some(Operator::Le),
+            ">" => Some(Operator::Gt),
+            ">=" => Some(Operator::Ge),
+            _ => None,
+        }
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Ne => ordering != Ordering::Equal,
+            Operator::Lt => ordering == Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Ge => ordering != Ordering::Less,
+        }
+    }
+}
+
+impl FromStr for Predicate {
+    type Err = PredicateError;
+
+    fn from_str(input: &str) -> Result<Predicate, PredicateError> {
+        let invalid = || PredicateError(input.to_owned());
+        let mut parts = input.trim().splitn(3, char::is_whitespace);
+        let (key, operator) = match (parts.next(), parts.next()) {
+            (Some(key), Some(operator)) if !key.is_empty() => (key, operator),
+            _ => return Err(invalid()),
+        };
+        // Values can contain spaces, "Hot Dog"
+        let value = parts.next().unwrap_or("").trim().to_owned();
+
+        if let Some(key) = key
+            .strip_prefix("count(")
+            .and_then(|key| key.strip_suffix(')'))
+        {
+            return Ok(Predicate::Count {
+                key: key.to_owned(),
+                operator: Operator::parse(operator).ok_or_else(invalid)?,
+                count: value.parse().map_err(|_| invalid())?,
+            });
+        }
+
+        let key = key.to_owned();
+        match operator {
+            "contains" | "!contains" => Ok(Predicate::Contains {
+                key,
+                value,
+                negated: operator.starts_with('!'),
+            }),
+            operator => Ok(Predicate::Compare {
+                key,
+                operator: Operator::parse(operator).ok_or_else(invalid)?,
+                value,
+            }),
+        }
+    }
+}
+
+impl Predicate {
+    /// Whether a serialized example satisfies the predicate
+    pub fn matches(&self, record: &[u8]) -> Result<bool, TfRecordError> {
+        let example: Example = protobuf::parse_from_bytes(record)?;
+        Ok(self.matches_example(&example))
+    }
+
+    // A missing feature has no values: it contains nothing, and has no first value to compare
+    fn matches_example(&self, example: &Example) -> bool {
+        let features = example.get_features().get_feature();
+        match self {
+            Predicate::Contains {
+                key,
+                value,
+                negated,
+            } => {
+                let found = match features.get(key) {
+                    Some(feature) => (0..len(feature))
+                        .any(|i| compare(feature, i, value) == Some(Ordering::Equal)),
+                    None => false,
+                };
+                found != *negated
+            }
+            Predicate::Compare {
+                key,
+                operator,
+                value,
+            } => match features.get(key).and_then(|f| compare(f, 0, value)) {
+                Some(ordering) => operator.holds(ordering),
+                None => false,
+            },
+            Predicate::Count {
+                key,
+                operator,
+                count,
+            } => {
+                let values = features.get(key).map_or(0, len);
+                operator.holds(values.cmp(count))
+            }
+        }
+    }
+}
+
+/// Whether a serialized example satisfies all the predicates, the example is only parsed when
+/// there are predicates
+pub fn matches_all(predicates: &[Predicate], record: &[u8]) -> Result<bool, TfRecordError> {
+    if predicates.is_empty() {
+        return Ok(true);
+    }
+    let example: Example = protobuf::parse_from_bytes(record)?;
+    Ok(predicates.iter().all(|p| p.matches_example(&example)))
+}
+
+// Number of values of a feature, whatever its type
+fn len(feature: &Feature) -> usize {
+    if feature.has_bytes_list() {
+        feature.get_bytes_list().get_value().len()
+    } else if feature.has_float_list() {
+        feature.get_float_list().get_value().len()
+    } else if feature.has_int64_list() {
+        feature.get_int64_list().get_value().len()
+    } else {
+        0
+    }
+}
+
+// Compare a value of a feature with the value of a predicate. Numbers are compared as numbers,
+// bytes as text. None when the feature has no such value, or the predicate value isn't a number.
+fn compare(feature: &Feature, index: usize, value: &str) -> Option<Ordering> {
+    if feature.has_bytes_list() {
+        let bytes = feature.get_bytes_list().get_value().get(index)?;
+        return Some(String::from_utf8_lossy(bytes).as_ref().cmp(value));
+    }
+
+    let number: f64 = value.parse().ok()?;
+    if feature.has_float_list() {
+        let float = feature.get_float_list().get_value().get(index)?;
+        f64::from(*float).partial_cmp(&number)
+    } else if feature.has_int64_list() {
+        let int = feature.get_int64_list().get_value().get(index)?;
+        (*int as f64).partial_cmp(&number)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Expected `<key> <operator> <value>` or `count(<key>) <operator> <count>`, got {0:?}")]
+pub struct PredicateError(pub String);
+
+#[test]
+fn test_predicate() {
+    use crate::pascal_voc::label_map::LabelMap;
+    use crate::pascal_voc::parser::Annotation;
+    use crate::pascal_voc::tfrecord::RecordBuilder;
+    use std::path::Path;
+
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+    let record = RecordBuilder::new(0, label_map)
+        .encode_example(Annotation::from_file(Path::new("./dataset/1.xml")).unwrap())
+        .unwrap()
+        .bytes;
+
+    let matches = |predicate: &str| {
+        predicate
+            .parse::<Predicate>()
+            .unwrap()
+            .matches(&record)
+            .unwrap()
+    };
+    assert!(matches("image/object/class/text contains hotdog"));
+    assert!(!matches("image/object/class/text !contains dog"));
+    assert!(matches("image/width >= 480"));
+    assert!(!matches("image/height > 360"));
+    assert!(matches("image/format == jpg"));
+    assert!(matches("count(image/object/bbox/xmin) == 2"));
+    assert!(matches("count(missing/key) < 1"));
+    assert!(!matches("missing/key != 1"));
+
+    assert!("image/width".parse::<Predicate>().is_err());
+    assert!("image/width ~ 1".parse::<Predicate>().is_err());
+    assert!("count(image/width) > many".parse::<Predicate>().is_err());
+}
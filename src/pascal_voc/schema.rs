@@ -0,0 +1,246 @@
+//! This module describes how examples are mapped to TensorFlow features.
+//! Training frameworks don't agree on feature keys and box formats, a schema lets
+//! the same dataset target any of them.
+use std::fs;
+use std::io::Error as IoError;
+use std::path::Path;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Feature keys used when writing examples. Fields without a key are not written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schema {
+    pub height: Option<String>,
+    pub width: Option<String>,
+    pub filename: Option<String>,
+    pub source_id: Option<String>,
+    pub key: Option<String>,
+    pub encoded: Option<String>,
+    pub format: Option<String>,
+    #[serde(default)]
+    pub bbox: BoxSchema,
+    pub class_text: Option<String>,
+    pub class_label: Option<String>,
+    /// Added to each class ID, use -1 for zero based labels.
+    #[serde(default)]
+    pub label_offset: i64,
+    pub difficult: Option<String>,
+    pub truncated: Option<String>,
+    pub view: Option<String>,
+    /// Views are written as text, or as their index in this list when it is set.
+    /// Views are compared without case.
+    #[serde(default)]
+    pub view_labels: Option<Vec<String>>,
+    pub annotation_path: Option<String>,
+    pub annotation_sha256: Option<String>,
+}
+
+/// Bounding boxes can be written one coordinate per feature, as a single flattened list, or both
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoxSchema {
+    pub xmin: Option<String>,
+    pub xmax: Option<String>,
+    pub ymin: Option<String>,
+    pub ymax: Option<String>,
+    /// Key of the flattened list, 4 values per box following `layout`
+    pub flattened: Option<String>,
+    #[serde(default)]
+    pub layout: BoxLayout,
+    #[serde(default)]
+    pub units: BoxUnits,
+}
+
+/// Order of the coordinates of a box in a flattened list
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoxLayout {
+    /// xmin, ymin, xmax, ymax
+    Xyxy,
+    /// ymin, xmin, ymax, xmax
+    Yxyx,
+    /// xmin, ymin, width, height
+    Xywh,
+    /// x center, y center, width, height
+    Cxcywh,
+}
+
+impl Default for BoxLayout {
+    fn default() -> Self {
+        BoxLayout::Xyxy
+    }
+}
+
+/// Coordinates are either normalized by the image size or in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoxUnits {
+    Normalized,
+    Absolute,
+}
+
+impl Default for BoxUnits {
+    fn default() -> Self {
+        BoxUnits::Normalized
+    }
+}
+
+impl Schema {
+    /// Get a built-in schema by name
+    pub fn from_name(name: &str) -> Option<Schema> {
+        match name {
+            "od-api" => Some(Schema::od_api()),
+            "tfds-voc" => Some(Schema::tfds_voc()),
+            "keras-cv" => Some(Schema::keras_cv()),
+            _ => None,
+        }
+    }
+
+    /// Load a custom schema from a TOML file
+    pub fn from_file(path: &Path) -> Result<Schema, SchemaError> {
+        let content = fs::read_to_string(path)?;
+        let schema = toml::from_str(&content)?;
+
+        Ok(schema)
+    }
+
+    /// TensorFlow object detection API, see:
+    /// https://github.com/tensorflow/models/blob/master/research/object_detection/g3doc/using_your_own_dataset.md
+    pub fn od_api() -> Schema {
+        Schema {
+            height: key("image/height"),
+            width: key("image/width"),
+            filename: key("image/filename"),
+            source_id: key("image/source_id"),
+            key: key("image/key/sha256"),
+            encoded: key("image/encoded"),
+            format: key("image/format"),
+            bbox: BoxSchema {
+                xmin: key("image/object/bbox/xmin"),
+                xmax: key("image/object/bbox/xmax"),
+                ymin: key("image/object/bbox/ymin"),
+                ymax: key("image/object/bbox/ymax"),
+                ..Default::default()
+            },
+            class_text: key("image/object/class/text"),
+            class_label: key("image/object/class/label"),
+            label_offset: 0,
+            difficult: key("image/object/difficult"),
+            truncated: key("image/object/truncated"),
+            view: key("image/object/view"),
+            view_labels: None,
+            annotation_path: key("image/provenance/annotation_path"),
+            annotation_sha256: key("image/provenance/annotation_sha256"),
+        }
+    }
+
+    /// Features of the `voc` dataset from TensorFlow Datasets
+    pub fn tfds_voc() -> Schema {
+        Schema {
+            height: None,
+            width: None,
+            filename: key("image/filename"),
+            source_id: None,
+            key: None,
+            encoded: key("image"),
+            format: None,
+            bbox: BoxSchema {
+                flattened: key("objects/bbox"),
+                layout: BoxLayout::Yxyx,
+                units: BoxUnits::Normalized,
+                ..Default::default()
+            },
+            class_text: None,
+            class_label: key("objects/label"),
+            label_offset: -1,
+            difficult: key("objects/is_difficult"),
+            truncated: key("objects/is_truncated"),
+            view: key("objects/pose"),
+            // Class label of the pose, in the order of the `voc` builder
+            view_labels: Some(
+                ["frontal", "rear", "left", "right", "unspecified"]
+                    .iter()
+                    .map(|view| view.to_string())
+                    .collect(),
+            ),
+            annotation_path: None,
+            annotation_sha256: None,
+        }
+    }
+
+    /// Dense bounding boxes in pixels, as used by KerasCV
+    pub fn keras_cv() -> Schema {
+        Schema {
+            height: key("image/height"),
+            width: key("image/width"),
+            filename: key("image/filename"),
+            source_id: None,
+            key: None,
+            encoded: key("image"),
+            format: None,
+            bbox: BoxSchema {
+                flattened: key("bounding_boxes/boxes"),
+                layout: BoxLayout::Xyxy,
+                units: BoxUnits::Absolute,
+                ..Default::default()
+            },
+            class_text: None,
+            class_label: key("bounding_boxes/classes"),
+            label_offset: -1,
+            difficult: None,
+            truncated: None,
+            view: None,
+            view_labels: None,
+            annotation_path: None,
+            annotation_sha256: None,
+        }
+    }
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Schema::od_api()
+    }
+}
+
+fn key(name: &str) -> Option<String> {
+    Some(name.to_owned())
+}
+
+/// Error types you might encounter while loading a schema
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    #[error("Io error while attempting to read the schema file")]
+    Io(#[from] IoError),
+
+    #[error("Failed to deserialize the schema file")]
+    Deserialize(#[from] toml::de::Error),
+}
+
+#[test]
+fn deserialize_schema() {
+    let schema: Schema = toml::from_str(
+        r#"
+        encoded = "image"
+        class_label = "objects/label"
+        label_offset = -1
+
+        [bbox]
+        flattened = "objects/bbox"
+        layout = "cxcywh"
+        units = "absolute"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(schema.encoded, key("image"));
+    assert_eq!(schema.class_label, key("objects/label"));
+    assert_eq!(schema.label_offset, -1);
+    assert_eq!(schema.filename, None);
+    assert_eq!(schema.bbox.flattened, key("objects/bbox"));
+    assert_eq!(schema.bbox.xmin, None);
+    assert_eq!(schema.bbox.layout, BoxLayout::Cxcywh);
+    assert_eq!(schema.bbox.units, BoxUnits::Absolute);
+}
@@ -0,0 +1,505 @@
+//! This module implements the strategies used to split a dataset between a training set
+//! and a test set. All of them are deterministic, the same input always gives the same split.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use super::parser::Annotation;
+use crate::math;
+
+/// How examples are assigned to the different sets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitMode {
+    /// Each example is assigned based on the hash of its image.
+    /// Ratios are close to the requested one on large datasets, rare classes might drift.
+    Hash,
+    /// Examples are grouped by their classes, and each group is split according to the requested
+    /// ratios. Adding images of new classes keeps the other images in their set, adding images of
+    /// existing classes moves a few images of these classes across sets.
+    Stratified,
+    /// Examples are ordered by hash and each set gets exactly the requested share.
+    /// Use a seed to draw different splits.
+    Exact,
+}
+
+impl Default for SplitMode {
+    fn default() -> Self {
+        SplitMode::Hash
+    }
+}
+
+/// A named set of examples, for instance `train`, and the share of the dataset it should get
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitSpec {
+    pub name: String,
+    /// Ratios of all sets are normalized, they don't have to add up to 1
+    pub ratio: f64,
+    /// Absolute number of examples, takes precedence over the ratio.
+    /// Sets without a count share what is left according to their ratios.
+    pub count: Option<usize>,
+}
+
+impl SplitSpec {
+    pub fn new(name: &str, ratio: f64) -> SplitSpec {
+        SplitSpec {
+            name: name.to_owned(),
+            ratio,
+            count: None,
+        }
+    }
+
+    pub fn with_count(name: &str, count: usize) -> SplitSpec {
+        SplitSpec {
+            name: name.to_owned(),
+            ratio: 0f64,
+            count: Some(count),
+        }
+    }
+}
+
+/// How to find examples that must stay in the same set, for instance consecutive frames of a video.
+/// Groups are assigned as a whole, based on the hash of their key instead of the image content.
+#[derive(Debug, Clone)]
+pub enum GroupBy {
+    /// Regex applied to the image file name. The key is the first capture group,
+    /// or the whole match if the regex has no group. Images that don't match are on their own.
+    Filename(Regex),
+    /// Images in the same directory are kept together
+    Directory,
+    /// Value of a field of the PASCAL-VOC file
+    Field(AnnotationField),
+}
+
+/// PASCAL-VOC fields that can be used to group examples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnnotationField {
+    /// `<folder>`
+    Folder,
+    /// `<path>`, the original path of the image
+    Path,
+    /// `<source><database>`
+    Database,
+}
+
+/// Options shared by all splitting strategies
+#[derive(Debug, Clone, Default)]
+pub struct SplitOpts {
+    pub mode: SplitMode,
+    pub group_by: Option<GroupBy>,
+    /// Salt added to hashes, different seeds give different splits
+    pub seed: Option<u64>,
+    /// Cluster of examples, by annotation path, for instance duplicate images.
+    /// Examples of a cluster are always assigned to the same set, along with their groups.
+    pub clusters: HashMap<PathBuf, usize>,
+}
+
+// Examples that are assigned to the same set
+#[derive(Debug)]
+struct Unit {
+    hash: u32,
+    members: Vec<usize>,
+}
+
+/// Split the dataset in as many sets as there are split specifications.
+/// Sets are returned in the same order as the specifications.
+pub fn split_dataset(
+    input: Vec<Annotation>,
+    splits: &[SplitSpec],
+    opts: &SplitOpts,
+) -> Vec<Vec<Annotation>> {
+    let units = get_units(&input, opts.group_by.as_ref(), opts.seed);
+    let units = merge_clusters(&input, units, &opts.clusters);
+    let ratios = effective_ratios(splits, units.len());
+    let assignments = match opts.mode {
+        SplitMode::Hash => hash_assignments(&input, &units, &ratios),
+        SplitMode::Stratified => stratified_assignments(&input, &units, &ratios),
+        SplitMode::Exact => exact_assignments(&input, &units, splits),
+    };
+
+    let mut sets: Vec<Vec<Annotation>> = splits.iter().map(|_| Vec::new()).collect();
+    input
+        .into_iter()
+        .zip(assignments)
+        .for_each(|(annotation, index)| sets[index].push(annotation));
+
+    sets
+}
+
+// Ratio of each set, sets with a fixed count get their share of the total number of units
+// and other sets share what is left
+fn effective_ratios(splits: &[SplitSpec], total: usize) -> Vec<f64> {
+    let fixed: f64 = splits
+        .iter()
+        .filter_map(|s| s.count)
+        .map(|count| count as f64 / total.max(1) as f64)
+        .sum();
+    let remaining = (1f64 - fixed).max(0f64);
+    let ratios: f64 = splits
+        .iter()
+        .filter(|s| s.count.is_none())
+        .map(|s| s.ratio)
+        .sum();
+
+    splits
+        .iter()
+        .map(|s| match s.count {
+            Some(count) => count as f64 / total.max(1) as f64,
+            None if ratios > 0f64 => s.ratio / ratios * remaining,
+            None => 0f64,
+        })
+        .collect()
+}
+
+// Without grouping, each example is its own unit and is hashed based on its image.
+// Unreadable images get the maximum hash, they end up in the last set.
+fn get_units(input: &[Annotation], group_by: Option<&GroupBy>, seed: Option<u64>) -> Vec<Unit> {
+    let group_by = match group_by {
+        Some(group_by) => group_by,
+        None => {
+            return input
+                .iter()
+                .enumerate()
+                .map(|(index, annotation)| Unit {
+                    hash: fs::read(&annotation.system_path)
+                        .map(|bytes| math::seeded_hash(&bytes, seed))
+                        .unwrap_or(u32::max_value()),
+                    members: vec![index],
+                })
+                .collect();
+        }
+    };
+
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    input.iter().enumerate().for_each(|(index, annotation)| {
+        groups
+            .entry(group_key(annotation, group_by))
+            .or_default()
+            .push(index);
+    });
+
+    groups
+        .into_iter()
+        .map(|(key, members)| Unit {
+            hash: math::seeded_hash(key.as_bytes(), seed),
+            members,
+        })
+        .collect()
+}
+
+// Merge units sharing a cluster. A merged unit takes the smallest hash of its parts.
+fn merge_clusters(
+    input: &[Annotation],
+    units: Vec<Unit>,
+    clusters: &HashMap<PathBuf, usize>,
+) -> Vec<Unit> {
+    if clusters.is_empty() {
+        return units;
+    }
+
+    let mut sets = math::DisjointSets::new(units.len());
+    let mut first_units: HashMap<usize, usize> = HashMap::new();
+    units.iter().enumerate().for_each(|(index, unit)| {
+        unit.members
+            .iter()
+            .filter_map(|member| clusters.get(&input[*member].annotation_path))
+            .for_each(|cluster| {
+                let first = *first_units.entry(*cluster).or_insert(index);
+                sets.union(first, index);
+            });
+    });
+
+    sets.sets()
+        .into_iter()
+        .map(|merged| Unit {
+            hash: merged
+                .iter()
+                .map(|index| units[*index].hash)
+                .min()
+                .unwrap_or(0),
+            members: merged
+                .iter()
+                .flat_map(|index| units[*index].members.iter().copied())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Key of the group an example belongs to
+pub fn group_key(annotation: &Annotation, group_by: &GroupBy) -> String {
+    match group_by {
+        GroupBy::Filename(regex) => regex
+            .captures(&annotation.filename)
+            .and_then(|captures| captures.get(1).or_else(|| captures.get(0)))
+            .map(|key| key.as_str().to_owned())
+            .unwrap_or_else(|| annotation.system_path.to_string_lossy().into_owned()),
+        GroupBy::Directory => annotation
+            .system_path
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        GroupBy::Field(AnnotationField::Folder) => annotation.folder.clone(),
+        GroupBy::Field(AnnotationField::Path) => annotation.path.to_string_lossy().into_owned(),
+        GroupBy::Field(AnnotationField::Database) => {
+            annotation.source.database.clone().unwrap_or_default()
+        }
+    }
+}
+
+// Each unit is assigned based on its hash
+fn hash_assignments(input: &[Annotation], units: &[Unit], ratios: &[f64]) -> Vec<usize> {
+    let mut assignments = vec![0; input.len()];
+    units.iter().for_each(|unit| {
+        let set = math::bucket(unit.hash, ratios);
+        unit.members
+            .iter()
+            .for_each(|index| assignments[*index] = set);
+    });
+
+    assignments
+}
+
+// Each unit is placed in a single stratum: the classes of its examples. Strata don't depend on
+// the rest of the dataset, new images leave the other strata as they are. Units of a stratum are
+// ordered by hash and dealt to each set according to the ratios, rounded down. The few units left
+// are assigned on their hash, so that small strata don't all end up in the largest set.
+fn stratified_assignments(input: &[Annotation], units: &[Unit], ratios: &[f64]) -> Vec<usize> {
+    let mut strata: BTreeMap<BTreeSet<&str>, Vec<(u32, usize)>> = BTreeMap::new();
+    units.iter().enumerate().for_each(|(index, unit)| {
+        let stratum = unit
+            .members
+            .iter()
+            .flat_map(|member| input[*member].objects.iter())
+            .map(|o| o.name.as_str())
+            .collect();
+
+        strata.entry(stratum).or_default().push((unit.hash, index));
+    });
+
+    let total: f64 = ratios.iter().sum();
+    let mut assignments = vec![0; input.len()];
+    strata.values_mut().for_each(|stratum| {
+        stratum.sort();
+        let mut stratum_units = stratum.iter();
+        ratios.iter().enumerate().for_each(|(set, ratio)| {
+            let count = (stratum.len() as f64 * ratio / total).floor() as usize;
+            stratum_units
+                .by_ref()
+                .take(count)
+                .flat_map(|(_, unit)| units[*unit].members.iter())
+                .for_each(|index| assignments[*index] = set);
+        });
+        stratum_units.for_each(|(hash, unit)| {
+            let set = math::bucket(*hash, ratios);
+            units[*unit]
+                .members
+                .iter()
+                .for_each(|index| assignments[*index] = set);
+        });
+    });
+
+    assignments
+}
+
+// Units are ordered by hash and dealt to each set, sets get exactly the requested number of units
+fn exact_assignments(input: &[Annotation], units: &[Unit], splits: &[SplitSpec]) -> Vec<usize> {
+    let mut order: Vec<(u32, usize)> = units
+        .iter()
+        .enumerate()
+        .map(|(index, unit)| (unit.hash, index))
+        .collect();
+    order.sort();
+
+    // Fixed counts first, then the remaining units are allocated according to the ratios
+    let mut remaining = units.len();
+    let mut counts: Vec<usize> = splits
+        .iter()
+        .map(|s| {
+            let count = s.count.unwrap_or(0).min(remaining);
+            remaining -= count;
+            count
+        })
+        .collect();
+    let ratios: Vec<f64> = splits
+        .iter()
+        .map(|s| if s.count.is_some() { 0f64 } else { s.ratio })
+        .collect();
+    math::allocate(remaining, &ratios)
+        .into_iter()
+        .enumerate()
+        .for_each(|(set, count)| counts[set] += count);
+
+    // Anything left, when all sets have a fixed count, goes to the last set
+    let mut assignments = vec![splits.len().saturating_sub(1); input.len()];
+    let mut ordered_units = order.iter();
+    counts.into_iter().enumerate().for_each(|(set, count)| {
+        ordered_units
+            .by_ref()
+            .take(count)
+            .flat_map(|(_, unit)| units[*unit].members.iter())
+            .for_each(|index| assignments[*index] = set);
+    });
+
+    assignments
+}
+
+/// Count how many images contain each class
+pub fn images_per_class(input: &[Annotation]) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    input.iter().for_each(|annotation| {
+        let mut names: Vec<&str> = annotation.objects.iter().map(|o| o.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        names
+            .into_iter()
+            .for_each(|name| *counts.entry(name).or_insert(0) += 1);
+    });
+
+    counts
+}
+
+#[test]
+fn test_stratified_split() {
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    let splits = vec![SplitSpec::new("test", 50.0), SplitSpec::new("train", 50.0)];
+
+    let opts = SplitOpts {
+        mode: SplitMode::Stratified,
+        ..Default::default()
+    };
+
+    // Strata are dog and hotdog (1 and 2), dog (3 and 4) and hotdog (5 and 6), each one is split
+    // in half
+    let sets = split_dataset(input.clone(), &splits, &opts);
+    let in_test = |names: &[&str]| {
+        sets[0]
+            .iter()
+            .filter(|a| names.contains(&a.filename.as_str()))
+            .count()
+    };
+    assert_eq!(sets[0].len() + sets[1].len(), input.len());
+    assert_eq!(in_test(&["1.jpg", "2.jpg", "3.jpg", "4.jpg"]), 2);
+    assert_eq!(in_test(&["5.jpg", "6.jpg"]), 1);
+
+    // Deterministic
+    let again = split_dataset(input.clone(), &splits, &opts);
+    let names = |set: &[Annotation]| set.iter().map(|a| a.filename.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&sets[0]), names(&again[0]));
+
+    // Images of hotdogs alone (5 and 6) are added to the others, which keep their set
+    let before = split_dataset(input[..4].to_vec(), &splits, &opts);
+    let kept = |set: &[Annotation]| {
+        names(set)
+            .into_iter()
+            .filter(|name| name != "5.jpg" && name != "6.jpg")
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(&before[0]), kept(&sets[0]));
+    assert_eq!(names(&before[1]), kept(&sets[1]));
+}
+
+#[test]
+fn test_group_by() {
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    let splits = vec![SplitSpec::new("test", 50.0), SplitSpec::new("train", 50.0)];
+
+    // All images are in the same directory, they can't be split
+    let opts = SplitOpts {
+        group_by: Some(GroupBy::Directory),
+        ..Default::default()
+    };
+    let sets = split_dataset(input.clone(), &splits, &opts);
+    assert!(sets.iter().any(|set| set.len() == input.len()));
+
+    // Two sessions, a (1 to 3) and b (4 to 6)
+    let input: Vec<Annotation> = input
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut annotation)| {
+            let session = if i < 3 { "a" } else { "b" };
+            annotation.filename = format!("session-{}_{}.jpg", session, i);
+            annotation
+        })
+        .collect();
+    let opts = SplitOpts {
+        mode: SplitMode::Stratified,
+        group_by: Some(GroupBy::Filename(Regex::new(r"^(.+)_\d+").unwrap())),
+        ..Default::default()
+    };
+    let sets = split_dataset(input, &splits, &opts);
+    sets.iter().for_each(|set| {
+        let a = set
+            .iter()
+            .filter(|a| a.filename.starts_with("session-a"))
+            .count();
+        assert!(a == 0 || a == set.len());
+    });
+    assert_eq!(sets[0].len(), 3);
+}
+
+#[test]
+fn test_exact_split() {
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    let names = |set: &[Annotation]| set.iter().map(|a| a.filename.clone()).collect::<Vec<_>>();
+    let mut opts = SplitOpts {
+        mode: SplitMode::Exact,
+        ..Default::default()
+    };
+
+    // 20% of 6 is 1.2, rounded to 1
+    let splits = vec![SplitSpec::new("test", 0.2), SplitSpec::new("train", 0.8)];
+    let sets = split_dataset(input.clone(), &splits, &opts);
+    assert_eq!(sets[0].len(), 1);
+    assert_eq!(sets[1].len(), 5);
+
+    let splits = vec![
+        SplitSpec::with_count("test", 4),
+        SplitSpec::new("train", 1.0),
+    ];
+    let sets = split_dataset(input.clone(), &splits, &opts);
+    assert_eq!(sets[0].len(), 4);
+    assert_eq!(sets[1].len(), 2);
+
+    // Seeds give reproducible splits
+    opts.seed = Some(42);
+    let first = split_dataset(input.clone(), &splits, &opts);
+    let second = split_dataset(input, &splits, &opts);
+    assert_eq!(names(&first[0]), names(&second[0]));
+    assert_eq!(first[0].len(), 4);
+}
+
+#[test]
+fn test_split_clusters() {
+    let input: Vec<Annotation> = (1..=6)
+        .map(|i| Annotation::from_file(&PathBuf::from(format!("./dataset/{}.xml", i))).unwrap())
+        .collect();
+    let splits = vec![SplitSpec::new("test", 50.0), SplitSpec::new("train", 50.0)];
+
+    // 1, 2 and 3 are duplicates of each other, as well as 4 and 5
+    let mut opts = SplitOpts::default();
+    vec![(1, 0), (2, 0), (3, 0), (4, 1), (5, 1)]
+        .into_iter()
+        .for_each(|(i, cluster)| {
+            let path = PathBuf::from(format!("./dataset/{}.xml", i));
+            opts.clusters.insert(path, cluster);
+        });
+
+    for mode in &[SplitMode::Hash, SplitMode::Stratified, SplitMode::Exact] {
+        opts.mode = *mode;
+        let sets = split_dataset(input.clone(), &splits, &opts);
+        let set_of = |name: &str| {
+            sets.iter()
+                .position(|set| set.iter().any(|a| a.filename == name))
+        };
+        assert_eq!(set_of("1.jpg"), set_of("2.jpg"));
+        assert_eq!(set_of("1.jpg"), set_of("3.jpg"));
+        assert_eq!(set_of("4.jpg"), set_of("5.jpg"));
+    }
+}
@@ -1,17 +1,20 @@
 //! This module implements helpers for generating tfrecord files for object detection.
 use std::collections::HashMap;
-use std::fs::{self, OpenOptions};
-use std::io::{BufWriter, Error as IoError};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error as IoError, ErrorKind, Read, Write};
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use protobuf::Message;
-use tensorflow::io::RecordWriter;
+use crc::crc32;
+use image::{DynamicImage, ImageError, ImageOutputFormat};
+use protobuf::{Message, ProtobufError};
+use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use super::label_map::LabelMap;
 use super::parser::Annotation;
-use crate::math;
+use super::schema::{BoxLayout, BoxSchema, BoxUnits, Schema};
+use crate::math::BBox;
 use crate::tensorflow_protos::example::Example;
 use crate::tensorflow_protos::feature::{Feature, Features};
 
@@ -28,8 +31,55 @@ pub struct RecordBuilder {
     // Current chunk
     // @TODO: currently unused, update when record splitting is implemented
     current_chunk: u64,
-    // Examples that should be part of the output tfrecord file
-    examples: Vec<ExampleImage>,
+    // Format used when an input image has to be transcoded
+    encoding: ImageEncoding,
+    // Whether examples should reference the annotation file they were built from
+    provenance: bool,
+    // Feature keys and formats of the output examples
+    schema: Schema,
+    // Serialized examples that should be part of the output tfrecord file
+    examples: Vec<Vec<u8>>,
+}
+
+/// Image formats that can be stored in the `image/encoded` feature.
+/// Images in any other format are transcoded to one of these.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageEncoding {
+    Jpeg,
+    Png,
+}
+
+impl Default for ImageEncoding {
+    fn default() -> Self {
+        ImageEncoding::Jpeg
+    }
+}
+
+impl ImageEncoding {
+    /// Value written to the `image/format` feature
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageEncoding::Jpeg => "jpeg",
+            ImageEncoding::Png => "png",
+        }
+    }
+}
+
+/// A serialized TensorFlow example, ready to be written to a tfrecord file
+#[derive(Debug)]
+pub struct EncodedExample {
+    pub bytes: Vec<u8>,
+    /// Conversion that was applied to the image, if any
+    pub transcoding: Option<Transcoding>,
+}
+
+/// Describes an image that was converted before being written to a tfrecord
+#[derive(Debug, Clone)]
+pub struct Transcoding {
+    /// Extension of the original image
+    pub from: String,
+    /// Format written to the tfrecord
+    pub to: ImageEncoding,
 }
 
 // Flat representation of an example
@@ -38,14 +88,24 @@ struct ExampleImage {
     height: i64,
     width: i64,
     filename: String,
+    key: String, // SHA-256 of the encoded image, as an hex string
     image_bytes: Vec<u8>,
     image_format: String,
-    xmins: Vec<f32>, // List of normalized left x coordinates in bounding box (1 per box)
-    xmaxs: Vec<f32>, // List of normalized right x coordinates in bounding box # (1 per box)
-    ymins: Vec<f32>, // List of normalized top y coordinates in bounding box (1 per box)
-    ymaxs: Vec<f32>, // List of normalized bottom y coordinates in bounding box # (1 per box)
+    boxes: Vec<BBox>,  // List of bounding boxes, normalized coordinates (1 per box)
     classes: Vec<i64>, // List of integer class id of bounding box (1 per box)
     classes_text: Vec<String>, // List of string class name of bounding box (1 per box)
+    difficult: Vec<i64>, // List of difficult flags, 0 or 1 (1 per box)
+    truncated: Vec<i64>, // List of truncated flags, 0 or 1 (1 per box)
+    views: Vec<String>, // List of poses (1 per box)
+    view_labels: Vec<i64>, // List of pose indexes, when the schema has view labels (1 per box)
+    provenance: Option<Provenance>,
+}
+
+// Where an example comes from
+#[derive(Debug, Default)]
+struct Provenance {
+    annotation_path: String,
+    annotation_sha256: String,
 }
 
 impl RecordBuilder {
@@ -58,146 +118,842 @@ impl RecordBuilder {
         }
     }
 
-    /// Add an example to the to the set
-    pub fn add_example(&mut self, example: Annotation) {
+    /// Set the format used for images that can't be stored as they are (BMP, TIFF, WebP...)
+    pub fn transcode_to(mut self, encoding: ImageEncoding) -> RecordBuilder {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Add the path and SHA-256 of the source annotation file to each example
+    pub fn with_provenance(mut self, provenance: bool) -> RecordBuilder {
+        self.provenance = provenance;
+        self
+    }
+
+    /// Set the schema used to map examples to TensorFlow features
+    pub fn with_schema(mut self, schema: Schema) -> RecordBuilder {
+        self.schema = schema;
+        self
+    }
+
+    /// Add an example to the to the set.
+    /// Returns the conversion that was applied to the image, if any.
+    pub fn add_example(
+        &mut self,
+        example: Annotation,
+    ) -> Result<Option<Transcoding>, TfRecordError> {
+        let encoded = self.encode_example(example)?;
+        self.current_size += encoded.bytes.len();
+        self.examples.push(encoded.bytes);
+
+        Ok(encoded.transcoding)
+    }
+
+    /// Size of the examples added so far, in bytes
+    pub fn size(&self) -> usize {
+        self.current_size
+    }
+
+    /// Build the serialized TensorFlow example for an annotation without adding it to the set.
+    /// Useful when the same example is written to several tfrecord files.
+    pub fn encode_example(&self, example: Annotation) -> Result<EncodedExample, TfRecordError> {
         let ext = example
             .path
             .extension()
             .and_then(|s| s.to_str())
-            .and_then(|ext| match ext.to_lowercase().as_ref() {
-                "png" | "jpg" | "jpeg" => Some(ext),
-                _ => None,
-            });
-
-        if let (Some(ext), Ok(bytes)) = (ext, fs::read(&example.system_path)) {
-            // First, map labels to their id and bail on error
-            let classes = if let Some(classes) = map_labels(&example, &self.label_map) {
-                classes
-            } else {
-                return;
-            };
+            .unwrap_or("")
+            .to_owned();
 
-            self.current_size += bytes.len();
-            let (xmins, xmaxs, ymins, ymaxs) = get_normalized_coordinates(&example);
-
-            let input = ExampleImage {
-                height: example.size.height as i64,
-                width: example.size.width as i64,
-                filename: example.filename.clone(),
-                image_bytes: bytes,
-                image_format: ext.to_owned(),
-                xmins,
-                xmaxs,
-                ymins,
-                ymaxs,
-                classes,
-                classes_text: example.objects.iter().map(|o| o.name.clone()).collect(),
-            };
+        // First, map labels to their id and bail on error
+        let classes = map_labels(&example, &self.label_map)?;
+        let view_labels = map_views(&example, &self.schema)?;
+        let bytes = fs::read(&example.system_path)?;
 
-            self.examples.push(input);
-        }
+        let (bytes, image_format, transcoding) = match ext.to_lowercase().as_ref() {
+            "png" | "jpg" | "jpeg" => (bytes, ext, None),
+            _ => {
+                let bytes = transcode(&bytes, self.encoding)
+                    .map_err(|e| TfRecordError::UnsupportedImage(ext.clone(), e))?;
+                let transcoding = Transcoding {
+                    from: ext.to_lowercase(),
+                    to: self.encoding,
+                };
+                (bytes, self.encoding.name().to_owned(), Some(transcoding))
+            }
+        };
+
+        let provenance = if self.provenance {
+            let annotation = fs::read(&example.annotation_path)?;
+            Some(Provenance {
+                annotation_path: example.annotation_path.to_string_lossy().into_owned(),
+                annotation_sha256: sha256(&annotation),
+            })
+        } else {
+            None
+        };
+
+        let input = ExampleImage {
+            height: example.size.height as i64,
+            width: example.size.width as i64,
+            filename: example.filename.clone(),
+            key: sha256(&bytes),
+            image_bytes: bytes,
+            image_format,
+            boxes: get_normalized_coordinates(&example),
+            classes,
+            classes_text: example.objects.iter().map(|o| o.name.clone()).collect(),
+            difficult: example.objects.iter().map(|o| o.difficult as i64).collect(),
+            truncated: example.objects.iter().map(|o| o.truncated as i64).collect(),
+            views: example.objects.iter().map(|o| o.pose.clone()).collect(),
+            view_labels,
+            provenance,
+        };
+
+        Ok(EncodedExample {
+            bytes: to_example(input, &self.schema).write_to_bytes()?,
+            transcoding,
+        })
     }
 
-    /// Write examples added to the builder to a tfrecord file
-    pub fn write_tfrecord(&mut self, path: &Path) -> Result<(), TfRecordError> {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(path)?;
+    /// Write examples added to the builder to `shards` tfrecord files in a directory,
+    /// see `write_shards`
+    pub fn write_tfrecord(
+        &mut self,
+        directory: &Path,
+        name: &str,
+        shards: usize,
+    ) -> Result<Vec<PathBuf>, TfRecordError> {
+        let examples = mem::take(&mut self.examples);
+        write_shards(directory, name, examples.iter().map(Vec::as_slice), shards)
+    }
+}
 
-        let buffered_writer = BufWriter::new(file);
-        let mut record_writer = RecordWriter::new(buffered_writer);
+/// Path of a tfrecord file of a set, `<name>.tfrecord` for a single shard.
+/// Otherwise shards follow the TensorFlow convention: `<name>-00001-of-00004.tfrecord`.
+pub fn shard_path(directory: &Path, name: &str, index: usize, shards: usize) -> PathBuf {
+    if shards <= 1 {
+        directory.join(format!("{}.tfrecord", name))
+    } else {
+        directory.join(format!("{}-{:05}-of-{:05}.tfrecord", name, index, shards))
+    }
+}
 
-        mem::take(&mut self.examples)
-            .into_iter()
-            .for_each(|example| {
-                let protobuf = Example::from(example);
+/// Write serialized examples to `shards` tfrecord files of similar sizes. Each record goes to
+/// the smallest shard so far, records keep their order within a shard.
+/// Returns the paths of the shards, in order.
+pub fn write_shards<'a, I>(
+    directory: &Path,
+    name: &str,
+    records: I,
+    shards: usize,
+) -> Result<Vec<PathBuf>, TfRecordError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let shards = shards.max(1);
+    let mut assigned: Vec<Vec<&[u8]>> = vec![Vec::new(); shards];
+    let mut sizes = vec![0usize; shards];
+    records.into_iter().for_each(|record| {
+        let smallest = (0..shards).min_by_key(|i| sizes[*i]).unwrap_or(0);
+        sizes[smallest] += record.len();
+        assigned[smallest].push(record);
+    });
+
+    assigned
+        .into_iter()
+        .enumerate()
+        .map(|(index, records)| {
+            let path = shard_path(directory, name, index, shards);
+            write_records(&path, records)?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Writes records to tfrecord shards as they come, without keeping them in memory.
+/// Each record goes to the smallest shard so far, so shards end up with similar sizes.
+pub struct ShardWriter {
+    shards: Vec<(BufWriter<File>, Shard)>,
+}
+
+/// A tfrecord file written by a `ShardWriter`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Shard {
+    pub path: PathBuf,
+    pub records: usize,
+    /// Size of the records, without their framing
+    pub bytes: usize,
+}
 
-                protobuf
-                    .write_to_bytes()
-                    .ok()
-                    .and_then(|bytes| record_writer.write_record(&bytes).ok());
-            });
+impl ShardWriter {
+    /// Create the `shards` files of a set in a directory, named as by `shard_path`
+    pub fn create(directory: &Path, name: &str, shards: usize) -> Result<Self, TfRecordError> {
+        let shards = shards.max(1);
+        let shards = (0..shards)
+            .map(|index| {
+                let path = shard_path(directory, name, index, shards);
+                let file = File::create(&path)?;
+                Ok((
+                    BufWriter::new(file),
+                    Shard {
+                        path,
+                        ..Default::default()
+                    },
+                ))
+            })
+            .collect::<Result<_, TfRecordError>>()?;
+        Ok(ShardWriter { shards })
+    }
+
+    pub fn write(&mut self, record: &[u8]) -> Result<(), TfRecordError> {
+        let smallest = (0..self.shards.len())
+            .min_by_key(|i| self.shards[*i].1.bytes)
+            .unwrap_or(0);
+        let (writer, shard) = &mut self.shards[smallest];
 
+        write_record(writer, record)?;
+        shard.records += 1;
+        shard.bytes += record.len();
         Ok(())
     }
+
+    /// Flush the files, returns the shards in order
+    pub fn finish(self) -> Result<Vec<Shard>, TfRecordError> {
+        self.shards
+            .into_iter()
+            .map(|(mut writer, shard)| {
+                writer.flush()?;
+                Ok(shard)
+            })
+            .collect()
+    }
+}
+
+/// Write serialized examples to a tfrecord file
+pub fn write_records<'a, I>(path: &Path, records: I) -> Result<(), TfRecordError>
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+
+    let mut writer = BufWriter::new(file);
+    for record in records {
+        write_record(&mut writer, record)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+// Write a record with its framing: its length, the data and masked CRC32-C checksums of both
+fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> Result<(), IoError> {
+    let length = (record.len() as u64).to_le_bytes();
+    writer.write_all(&length)?;
+    writer.write_all(&masked_crc(&length).to_le_bytes())?;
+    writer.write_all(record)?;
+    writer.write_all(&masked_crc(record).to_le_bytes())
+}
+
+/// Read serialized examples from a tfrecord file. Each record is made of its length, the data
+/// and masked CRC32-C checksums of both, records with a wrong checksum are rejected.
+/// The whole file is held in memory, commands stream records with `RecordReader` instead.
+#[cfg(test)]
+pub fn read_records(path: &Path) -> Result<Vec<Vec<u8>>, TfRecordError> {
+    RecordReader::open(path)?.collect()
+}
+
+/// Reads the records of a tfrecord file one at a time, for files that don't fit in memory.
+/// The iteration stops after the first error.
+pub struct RecordReader<R> {
+    reader: R,
+    // Index of the next record
+    index: usize,
+    failed: bool,
+}
+
+impl RecordReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self, TfRecordError> {
+        Ok(RecordReader::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(reader: R) -> Self {
+        RecordReader {
+            reader,
+            index: 0,
+            failed: false,
+        }
+    }
+
+    // Next record, None at the end of the input
+    fn read_record(&mut self) -> Result<Option<Vec<u8>>, TfRecordError> {
+        let corrupted = TfRecordError::CorruptedRecord(self.index);
+        let mut header = [0u8; 12];
+        match read_full(&mut self.reader, &mut header)? {
+            0 => return Ok(None),
+            12 => {}
+            _ => return Err(corrupted),
+        }
+
+        let (length_bytes, length_crc) = header.split_at(8);
+        if masked_crc(length_bytes) != read_u32(length_crc) {
+            return Err(corrupted);
+        }
+        let mut length = [0u8; 8];
+        length.copy_from_slice(length_bytes);
+        let length = u64::from_le_bytes(length) as usize;
+
+        // Read the data and its checksum, an absurd length fails like a truncated record
+        let mut data = Vec::new();
+        let read = (&mut self.reader)
+            .take(length as u64)
+            .read_to_end(&mut data)?;
+        let mut data_crc = [0u8; 4];
+        if read != length || read_full(&mut self.reader, &mut data_crc)? != 4 {
+            return Err(corrupted);
+        }
+        if masked_crc(&data) != read_u32(&data_crc) {
+            return Err(corrupted);
+        }
+
+        self.index += 1;
+        Ok(Some(data))
+    }
 }
 
-// Map labels to integers. Option is Some if all operations succeed
-fn map_labels(input: &Annotation, label_map: &LabelMap) -> Option<Vec<i64>> {
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = Result<Vec<u8>, TfRecordError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+// Fill the buffer, unless the input ends first. Returns the number of bytes read.
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> Result<usize, IoError> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// CRC32-C of some bytes, masked as described by the tfrecord format
+fn masked_crc(bytes: &[u8]) -> u32 {
+    let crc = crc32::checksum_castagnoli(bytes);
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(bytes);
+    u32::from_le_bytes(value)
+}
+
+/// An example read back from a tfrecord file
+#[derive(Debug, Default)]
+pub struct DecodedExample {
+    pub filename: Option<String>,
+    /// Encoded image, as stored in the example
+    pub image: Vec<u8>,
+    /// Bounding boxes, in the units of the schema
+    pub boxes: Vec<BBox>,
+    pub units: BoxUnits,
+    /// Class of each box: its text, its label, or both as `text (label)`
+    pub classes: Vec<String>,
+}
+
+/// Parse a serialized example, features are looked up with the keys of the schema
+pub fn decode_example(bytes: &[u8], schema: &Schema) -> Result<DecodedExample, TfRecordError> {
+    let example: Example = protobuf::parse_from_bytes(bytes)?;
+    let features = example.get_features().get_feature();
+
+    let image = get_bytes(features, &schema.encoded)
+        .and_then(|values| values.first())
+        .ok_or_else(|| TfRecordError::MissingFeature(schema.encoded.clone().unwrap_or_default()))?;
+    let filename = get_bytes(features, &schema.filename)
+        .and_then(|values| values.first())
+        .map(|name| String::from_utf8_lossy(name).into_owned());
+
+    let boxes: Vec<BBox> = match get_floats(features, &schema.bbox.flattened) {
+        Some(values) => values
+            .chunks_exact(4)
+            .map(|c| {
+                let (a, b, c, d) = (c[0] as f64, c[1] as f64, c[2] as f64, c[3] as f64);
+                match schema.bbox.layout {
+                    BoxLayout::Xyxy => BBox::from_xyxy(a, b, c, d),
+                    BoxLayout::Yxyx => BBox::from_yxyx(a, b, c, d),
+                    BoxLayout::Xywh => BBox::from_xywh(a, b, c, d),
+                    BoxLayout::Cxcywh => BBox::from_cxcywh(a, b, c, d),
+                }
+            })
+            .collect(),
+        None => {
+            let coordinates = |key: &Option<String>| get_floats(features, key).unwrap_or(&[]);
+            let xmins = coordinates(&schema.bbox.xmin);
+            let ymins = coordinates(&schema.bbox.ymin);
+            let xmaxs = coordinates(&schema.bbox.xmax);
+            let ymaxs = coordinates(&schema.bbox.ymax);
+            (0..xmins
+                .len()
+                .min(ymins.len())
+                .min(xmaxs.len())
+                .min(ymaxs.len()))
+                .map(|i| {
+                    BBox::from_xyxy(
+                        xmins[i] as f64,
+                        ymins[i] as f64,
+                        xmaxs[i] as f64,
+                        ymaxs[i] as f64,
+                    )
+                })
+                .collect()
+        }
+    };
+
+    let (texts, labels) = classes_of(features, schema);
+    let classes = (0..boxes.len())
+        .map(|i| match (texts.get(i).cloned(), labels.get(i).copied()) {
+            (Some(text), Some(label)) => format!("{} ({})", text, label),
+            (Some(text), None) => text,
+            (None, Some(label)) => label.to_string(),
+            (None, None) => String::new(),
+        })
+        .collect();
+
+    Ok(DecodedExample {
+        filename,
+        image: image.clone(),
+        boxes,
+        units: schema.bbox.units,
+        classes,
+    })
+}
+
+/// Class texts and labels of a serialized example. Labels are label map IDs, the label offset of
+/// the schema is removed. Either list is empty when the schema or the example doesn't have it.
+pub fn decode_classes(
+    bytes: &[u8],
+    schema: &Schema,
+) -> Result<(Vec<String>, Vec<i64>), TfRecordError> {
+    let example: Example = protobuf::parse_from_bytes(bytes)?;
+    Ok(classes_of(example.get_features().get_feature(), schema))
+}
+
+fn classes_of(features: &HashMap<String, Feature>, schema: &Schema) -> (Vec<String>, Vec<i64>) {
+    let texts = get_bytes(features, &schema.class_text)
+        .unwrap_or(&[])
+        .iter()
+        .map(|t| String::from_utf8_lossy(t).into_owned())
+        .collect();
+    let labels = get_ints(features, &schema.class_label)
+        .unwrap_or(&[])
+        .iter()
+        .map(|l| l - schema.label_offset)
+        .collect();
+    (texts, labels)
+}
+
+// Helper functions, get the values of a feature if the schema has a key for it
+fn get_floats<'a>(
+    features: &'a HashMap<String, Feature>,
+    key: &Option<String>,
+) -> Option<&'a [f32]> {
+    key.as_ref()
+        .and_then(|key| features.get(key))
+        .filter(|feature| feature.has_float_list())
+        .map(|feature| feature.get_float_list().get_value())
+}
+
+fn get_ints<'a>(features: &'a HashMap<String, Feature>, key: &Option<String>) -> Option<&'a [i64]> {
+    key.as_ref()
+        .and_then(|key| features.get(key))
+        .filter(|feature| feature.has_int64_list())
+        .map(|feature| feature.get_int64_list().get_value())
+}
+
+fn get_bytes<'a>(
+    features: &'a HashMap<String, Feature>,
+    key: &Option<String>,
+) -> Option<&'a [Vec<u8>]> {
+    key.as_ref()
+        .and_then(|key| features.get(key))
+        .filter(|feature| feature.has_bytes_list())
+        .map(|feature| feature.get_bytes_list().get_value())
+}
+
+// Map labels to integers, fails on the first label missing from the map
+fn map_labels(input: &Annotation, label_map: &LabelMap) -> Result<Vec<i64>, TfRecordError> {
     input
         .objects
         .iter()
-        .map(|object| label_map.get(&object.name))
+        .map(|object| {
+            label_map
+                .get(&object.name)
+                .ok_or_else(|| TfRecordError::UnknownLabel(object.name.clone()))
+        })
         .collect()
 }
 
-// Outputs vectors of normalized coordinates, tuple structure is (xmins, xmaxs, ymins, ymaxs)
-fn get_normalized_coordinates(input: &Annotation) -> (Vec<f32>, Vec<f32>, Vec<f32>, Vec<f32>) {
-    let labels_count = input.objects.len();
-    let width = input.size.width;
-    let height = input.size.height;
-    let mut xmins = Vec::with_capacity(labels_count);
-    let mut xmaxs = Vec::with_capacity(labels_count);
-    let mut ymins = Vec::with_capacity(labels_count);
-    let mut ymaxs = Vec::with_capacity(labels_count);
-
-    input.objects.iter().for_each(|object| {
-        xmins.push(math::normalize(object.bndbox.xmin, 0, width) as f32);
-        xmaxs.push(math::normalize(object.bndbox.xmax, 0, width) as f32);
-        ymins.push(math::normalize(object.bndbox.ymin, 0, height) as f32);
-        ymaxs.push(math::normalize(object.bndbox.ymax, 0, height) as f32);
-    });
+// Index of the view of each object in the view labels of the schema, empty without view labels
+fn map_views(input: &Annotation, schema: &Schema) -> Result<Vec<i64>, TfRecordError> {
+    let labels = match &schema.view_labels {
+        Some(labels) => labels,
+        None => return Ok(Vec::new()),
+    };
+    input
+        .objects
+        .iter()
+        .map(|object| {
+            labels
+                .iter()
+                .position(|label| label.eq_ignore_ascii_case(object.pose.trim()))
+                .map(|index| index as i64)
+                .ok_or_else(|| TfRecordError::UnknownView(object.pose.clone()))
+        })
+        .collect()
+}
 
-    (xmins, xmaxs, ymins, ymaxs)
+// Hex encoded SHA-256 of some bytes
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
 }
 
-// Map our internal representation of an example to the generic version used by TensorFlow
-impl From<ExampleImage> for Example {
-    fn from(input: ExampleImage) -> Example {
-        let mut output = Example::new();
-        let mut features = Features::new();
-        let mut features_map = HashMap::new();
+// Decode an image and encode it again in one of the formats accepted by TensorFlow
+fn transcode(bytes: &[u8], encoding: ImageEncoding) -> Result<Vec<u8>, ImageError> {
+    let image = image::load_from_memory(bytes)?;
+    let mut output = Vec::new();
+
+    match encoding {
+        // JPEG has no alpha channel and only supports 8 bits per channel
+        ImageEncoding::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut output, ImageOutputFormat::Jpeg(95))?,
+        ImageEncoding::Png => image.write_to(&mut output, ImageOutputFormat::Png)?,
+    }
 
-        insert_feature(&mut features_map, "image/height", input.height);
-        insert_feature(&mut features_map, "image/width", input.width);
+    Ok(output)
+}
 
-        // According to the docs, "image/filename" and "image/source_id"
-        // are both based on file name, see python sample code:
-        // https://github.com/tensorflow/models/blob/master/research/object_detection/g3doc/using_your_own_dataset.md
-        let source_id = input.filename.clone();
-        insert_feature(&mut features_map, "image/filename", input.filename);
-        insert_feature(&mut features_map, "image/source_id", source_id);
-        insert_feature(&mut features_map, "image/encoded", input.image_bytes);
-        insert_feature(&mut features_map, "image/format", input.image_format);
-        insert_feature(&mut features_map, "image/object/bbox/xmin", input.xmins);
-        insert_feature(&mut features_map, "image/object/bbox/xmax", input.xmaxs);
-        insert_feature(&mut features_map, "image/object/bbox/ymin", input.ymins);
-        insert_feature(&mut features_map, "image/object/bbox/ymax", input.ymaxs);
-        insert_feature(&mut features_map, "image/object/class/text", input.classes);
-        insert_feature(
-            &mut features_map,
-            "image/object/class/label",
-            input.classes_text,
-        );
+// Outputs the normalized bounding box of each object
+fn get_normalized_coordinates(input: &Annotation) -> Vec<BBox> {
+    let width = input.size.width as f64;
+    let height = input.size.height as f64;
 
-        features.set_feature(features_map);
-        output.set_features(features);
+    input
+        .objects
+        .iter()
+        .map(|object| BBox::from(&object.bndbox).normalize(width, height))
+        .collect()
+}
 
-        output
+// Map our internal representation of an example to the generic version used by TensorFlow,
+// feature keys and box formats are defined by the schema
+fn to_example(input: ExampleImage, schema: &Schema) -> Example {
+    let mut output = Example::new();
+    let mut features = Features::new();
+    let mut features_map = HashMap::new();
+    let map = &mut features_map;
+
+    let flattened_boxes = flatten_boxes(&input, &schema.bbox);
+    let boxes = scale_boxes(&input, schema.bbox.units);
+    let coordinates = |coordinate: fn(&BBox) -> f64| {
+        boxes
+            .iter()
+            .map(|b| coordinate(b) as f32)
+            .collect::<Vec<_>>()
+    };
+    let classes = input
+        .classes
+        .into_iter()
+        .map(|id| id + schema.label_offset)
+        .collect::<Vec<_>>();
+
+    insert_feature(map, &schema.height, input.height);
+    insert_feature(map, &schema.width, input.width);
+
+    // According to the docs, "image/filename" and "image/source_id"
+    // are both based on file name, see python sample code:
+    // https://github.com/tensorflow/models/blob/master/research/object_detection/g3doc/using_your_own_dataset.md
+    let source_id = input.filename.clone();
+    insert_feature(map, &schema.filename, input.filename);
+    insert_feature(map, &schema.source_id, source_id);
+    insert_feature(map, &schema.key, input.key);
+    insert_feature(map, &schema.encoded, input.image_bytes);
+    insert_feature(map, &schema.format, input.image_format);
+    insert_feature(map, &schema.bbox.xmin, coordinates(|b| b.xmin));
+    insert_feature(map, &schema.bbox.xmax, coordinates(|b| b.xmax));
+    insert_feature(map, &schema.bbox.ymin, coordinates(|b| b.ymin));
+    insert_feature(map, &schema.bbox.ymax, coordinates(|b| b.ymax));
+    insert_feature(map, &schema.bbox.flattened, flattened_boxes);
+    insert_feature(map, &schema.class_text, input.classes_text);
+    insert_feature(map, &schema.class_label, classes);
+    insert_feature(map, &schema.difficult, input.difficult);
+    insert_feature(map, &schema.truncated, input.truncated);
+    match schema.view_labels {
+        Some(_) => insert_feature(map, &schema.view, input.view_labels),
+        None => insert_feature(map, &schema.view, input.views),
     }
+
+    if let Some(provenance) = input.provenance {
+        insert_feature(map, &schema.annotation_path, provenance.annotation_path);
+        insert_feature(map, &schema.annotation_sha256, provenance.annotation_sha256);
+    }
+
+    features.set_feature(features_map);
+    output.set_features(features);
+
+    output
 }
 
-// Helper function, converts a list of values into a TensorFlow Feature and insert it into a map
-fn insert_feature<V: Into<Feature>>(map: &mut HashMap<String, Feature>, attr: &str, values: V) {
-    let attr = String::from(attr);
-    map.insert(attr, values.into());
+// Flatten boxes into a single list, 4 values per box in the order defined by the layout
+fn flatten_boxes(input: &ExampleImage, schema: &BoxSchema) -> Vec<f32> {
+    scale_boxes(input, schema.units)
+        .iter()
+        .flat_map(|b| {
+            let values = match schema.layout {
+                BoxLayout::Xyxy => b.to_xyxy(),
+                BoxLayout::Yxyx => b.to_yxyx(),
+                BoxLayout::Xywh => b.to_xywh(),
+                BoxLayout::Cxcywh => b.to_cxcywh(),
+            };
+            values.to_vec()
+        })
+        .map(|value| value as f32)
+        .collect()
+}
+
+// Boxes in the requested units, absolute coordinates are rounded to the nearest pixel
+fn scale_boxes(input: &ExampleImage, units: BoxUnits) -> Vec<BBox> {
+    input
+        .boxes
+        .iter()
+        .map(|b| match units {
+            BoxUnits::Normalized => *b,
+            BoxUnits::Absolute => b
+                .denormalize(input.width as f64, input.height as f64)
+                .round(),
+        })
+        .collect()
+}
+
+// Helper function, converts a list of values into a TensorFlow Feature and insert it into a map.
+// Does nothing if the schema has no key for this feature.
+fn insert_feature<V: Into<Feature>>(
+    map: &mut HashMap<String, Feature>,
+    attr: &Option<String>,
+    values: V,
+) {
+    if let Some(attr) = attr {
+        map.insert(attr.clone(), values.into());
+    }
 }
 
 /// Error types you might encounter while working with tfrecord files
 #[derive(Debug, Error)]
 pub enum TfRecordError {
-    #[error("Io error while attempting to write tfrecord file")]
+    #[error("Io error while attempting to build tfrecord file")]
     Io(#[from] IoError),
+
+    #[error("Label {0:?} is missing from the label map")]
+    UnknownLabel(String),
+
+    #[error("View {0:?} is not one of the view labels of the schema")]
+    UnknownView(String),
+
+    #[error("Could not transcode {0:?} image")]
+    UnsupportedImage(String, #[source] ImageError),
+
+    #[error("Could not serialize or parse example")]
+    Protobuf(#[from] ProtobufError),
+
+    #[error("Record {0} is corrupted or truncated")]
+    CorruptedRecord(usize),
+
+    #[error("Feature {0:?} is missing from the example")]
+    MissingFeature(String),
+}
+
+#[test]
+fn test_sha256() {
+    assert_eq!(
+        sha256(b"abc"),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+}
+
+#[test]
+fn test_flatten_boxes() {
+    let input = ExampleImage {
+        width: 100,
+        height: 50,
+        boxes: vec![
+            BBox::from_xyxy(0.1, 0.2, 0.5, 0.6),
+            BBox::from_xyxy(0.0, 0.0, 1.0, 1.0),
+        ],
+        ..Default::default()
+    };
+
+    let mut schema = BoxSchema::default();
+    assert_eq!(
+        flatten_boxes(&input, &schema),
+        vec![0.1, 0.2, 0.5, 0.6, 0.0, 0.0, 1.0, 1.0]
+    );
+
+    schema.layout = BoxLayout::Cxcywh;
+    schema.units = BoxUnits::Absolute;
+    assert_eq!(
+        flatten_boxes(&input, &schema),
+        vec![30.0, 20.0, 40.0, 20.0, 50.0, 25.0, 100.0, 50.0]
+    );
+}
+
+#[test]
+fn test_read_records() {
+    use std::env;
+
+    let path = env::temp_dir().join("tfrecord_test_read_records.tfrecord");
+    let records: Vec<&[u8]> = vec![b"first", b"", b"third record"];
+    write_records(&path, records.clone()).unwrap();
+    assert_eq!(read_records(&path).unwrap(), records);
+
+    // Flip a bit of the last record
+    let mut bytes = fs::read(&path).unwrap();
+    let last = bytes.len() - 6;
+    bytes[last] ^= 1;
+    fs::write(&path, &bytes).unwrap();
+    assert!(matches!(
+        read_records(&path),
+        Err(TfRecordError::CorruptedRecord(2))
+    ));
+
+    // Write errors are reported, /dev/full fails on every write
+    let full = Path::new("/dev/full");
+    if full.exists() {
+        assert!(write_records(full, records).is_err());
+    }
+}
+
+#[test]
+fn test_write_shards() {
+    use std::env;
+
+    let directory = env::temp_dir();
+    let records: Vec<&[u8]> = vec![b"aaaa", b"b", b"c", b"dd"];
+    let paths = write_shards(&directory, "test_write_shards", records, 2).unwrap();
+    assert_eq!(
+        paths[1],
+        directory.join("test_write_shards-00001-of-00002.tfrecord")
+    );
+
+    let shards: Vec<Vec<Vec<u8>>> = paths.iter().map(|p| read_records(p).unwrap()).collect();
+    assert_eq!(shards[0], vec![b"aaaa".to_vec()]);
+    assert_eq!(
+        shards[1],
+        vec![b"b".to_vec(), b"c".to_vec(), b"dd".to_vec()]
+    );
+    assert_eq!(
+        shard_path(&directory, "train", 0, 1),
+        directory.join("train.tfrecord")
+    );
+}
+
+#[test]
+fn test_decode_example() {
+    let annotation = Annotation::from_file(Path::new("./dataset/1.xml")).unwrap();
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+
+    for schema in &[Schema::od_api(), Schema::tfds_voc(), Schema::keras_cv()] {
+        let record = RecordBuilder::new(0, label_map.clone()).with_schema(schema.clone());
+        let bytes = record.encode_example(annotation.clone()).unwrap().bytes;
+        let decoded = decode_example(&bytes, schema).unwrap();
+
+        assert_eq!(decoded.image, fs::read("./dataset/1.jpg").unwrap());
+        assert_eq!(decoded.boxes.len(), 2);
+        let first = match decoded.units {
+            BoxUnits::Normalized => decoded.boxes[0].denormalize(480.0, 360.0).round(),
+            BoxUnits::Absolute => decoded.boxes[0],
+        };
+        assert_eq!(first, BBox::from_xyxy(85.0, 1.0, 381.0, 244.0));
+        assert!(decoded.classes[0] == "dog (1)" || decoded.classes[0] == "1");
+    }
+}
+
+#[test]
+fn test_object_features() {
+    let mut annotation = Annotation::from_file(Path::new("./dataset/1.xml")).unwrap();
+    annotation.objects[1].difficult = true;
+    annotation.objects[1].truncated = false;
+    annotation.objects[1].pose = "Left".to_owned();
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+
+    let bytes = RecordBuilder::new(0, label_map)
+        .encode_example(annotation)
+        .unwrap()
+        .bytes;
+    let example: Example = protobuf::parse_from_bytes(&bytes).unwrap();
+    let features = example.get_features().get_feature();
+    let flags = |key: &str| features[key].get_int64_list().get_value().to_vec();
+    assert_eq!(flags("image/object/difficult"), vec![0, 1]);
+    assert_eq!(flags("image/object/truncated"), vec![1, 0]);
+    let views = features["image/object/view"].get_bytes_list().get_value();
+    assert_eq!(views, [b"Unspecified".to_vec(), b"Left".to_vec()]);
+}
+
+#[test]
+fn test_view_labels() {
+    let mut annotation = Annotation::from_file(Path::new("./dataset/1.xml")).unwrap();
+    annotation.objects[1].pose = "Left".to_owned();
+    let mut label_map = LabelMap::new();
+    label_map.add("dog");
+    label_map.add("hotdog");
+    let record = RecordBuilder::new(0, label_map).with_schema(Schema::tfds_voc());
+
+    // TFDS stores the pose as a class label
+    let bytes = record.encode_example(annotation.clone()).unwrap().bytes;
+    let example: Example = protobuf::parse_from_bytes(&bytes).unwrap();
+    let pose = &example.get_features().get_feature()["objects/pose"];
+    assert!(pose.has_int64_list());
+    assert_eq!(pose.get_int64_list().get_value(), [4, 2]);
+
+    annotation.objects[1].pose = "Upside down".to_owned();
+    assert!(matches!(
+        record.encode_example(annotation),
+        Err(TfRecordError::UnknownView(_))
+    ));
+}
+
+#[test]
+fn test_transcode() {
+    use image::GenericImageView;
+
+    let jpeg = include_bytes!("../../dataset/1.jpg");
+    let png = transcode(jpeg, ImageEncoding::Png).unwrap();
+    let decoded = image::load_from_memory(&png).unwrap();
+    assert_eq!(image::guess_format(&png).unwrap(), image::ImageFormat::Png);
+    assert_eq!(decoded.dimensions(), (480, 360));
+
+    let jpeg = transcode(&png, ImageEncoding::Jpeg).unwrap();
+    assert_eq!(
+        image::guess_format(&jpeg).unwrap(),
+        image::ImageFormat::Jpeg
+    );
 }
@@ -0,0 +1,45 @@
+//! Progress bars for long running commands. They are drawn on stderr, and hidden when progress
+//! is disabled or when stderr is not a terminal, for instance in CI logs.
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Creates progress bars, all of them are hidden when progress is disabled
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Progress {
+        Progress { enabled }
+    }
+
+    /// Counter for a task of unknown length, such as walking a directory
+    pub fn spinner(&self, name: &str) -> ProgressBar {
+        let bar = self.target(ProgressBar::new_spinner());
+        bar.set_style(ProgressStyle::default_spinner().template("{spinner} {prefix}: {pos}"));
+        bar.set_prefix(name);
+        bar
+    }
+
+    /// Bar for a task of known length, with its estimated time left.
+    /// The message of the bar is shown after the counts, for instance the number of bytes written.
+    pub fn bar(&self, len: usize, name: &str) -> ProgressBar {
+        let bar = self.target(ProgressBar::new(len as u64));
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{prefix:20} [{bar:40}] {pos}/{len} {msg} ({eta} left)")
+                .progress_chars("=> "),
+        );
+        bar.set_prefix(name);
+        bar
+    }
+
+    fn target(&self, bar: ProgressBar) -> ProgressBar {
+        if self.enabled {
+            bar.set_draw_target(ProgressDrawTarget::stderr());
+        } else {
+            bar.set_draw_target(ProgressDrawTarget::hidden());
+        }
+        bar
+    }
+}